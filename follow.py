@@ -0,0 +1,22 @@
+#!/usr/bin/python
+# Follow a peer's view. Usage: follow.py <peer ipv6 addr>
+# Panning or zooming by hand stops following.
+import sys
+from pytherapy import PushApi, ReqApi, Event, Notifier
+
+peer = sys.argv[1]
+
+api = PushApi()
+reqapi = ReqApi()
+reqapi.follow_view(peer)
+
+presenter = Notifier(addr=peer)
+presenter.set_filter(Event.VIEW_CHANGED)
+
+try:
+    for ev in presenter:
+        if ev.type != Event.VIEW_CHANGED:
+            continue
+        api.remote_view(peer, ev.x, ev.y, ev.zoom, ev.rotation)
+except KeyboardInterrupt:
+    reqapi.follow_view("")
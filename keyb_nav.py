@@ -1,11 +1,17 @@
 #!/usr/bin/python
 # Keyboard navigation
-from pytherapy import PushApi, Event, MouseButton, Notifier
+from pytherapy import PushApi, ReqApi, Event, MouseButton, Notifier
 
 api = PushApi()
+reqapi = ReqApi()
 notify = Notifier()
 
+# Last known mouse position in world coords
+cursor_pos = (0., 0.)
+is_minimap_visible = False
+
 def pan_view(ev):
+    global is_minimap_visible
     match ev.key:
         case "Left":
             api.pan(0.01, 0.)
@@ -19,15 +25,27 @@ def pan_view(ev):
             api.zoom(0.95)
         case "X":
             api.zoom(1.05)
+        case "Q":
+            api.rotate_at(0.05, *cursor_pos)
+        case "E":
+            api.rotate_at(-0.05, *cursor_pos)
+        case "Home":
+            reqapi.fit_all(0.5)
+        case "M":
+            is_minimap_visible = not is_minimap_visible
+            api.set_minimap(is_minimap_visible)
 
 def wheel_zoom(ev):
-    api.zoom(1 + ev.y/10)
+    # Keep whatever is under the mouse in place
+    api.zoom_at(1 + ev.y/10, *cursor_pos)
     print(f"Mouse wheel: ({ev.x}, {ev.y})")
 
 for ev in notify:
     match ev.type:
         case Event.KEY_DOWN:
             pan_view(ev)
+        case Event.MOUSE_MOTION:
+            cursor_pos = (ev.x, ev.y)
         case Event.MOUSE_WHEEL:
             wheel_zoom(ev)
 
@@ -1,9 +1,11 @@
 #!/usr/bin/python
 # Mouse pencil tool
+import time
 import python_libinput
-from pytherapy import PushApi, ReqApi, Event, MouseButton, Notifier
+from pytherapy import PushApi, ReqApi, Event, MouseButton, Notifier, Status
 
-CURSOR_SIZE = 0.02
+# In screen pixels
+CURSOR_SIZE = 12
 CURSOR_COLOR = (1.0, 0.0, 0.0, 1.0)
 LINE_COLOR = (1.0, 0.0, 0.0, 1.0)
 
@@ -29,18 +31,22 @@ def draw_line(layer_name, x1, y1, x2, y2, thickness, r, g, b, a):
 li = python_libinput.libinput()
 assert li.start()
 
-reqapi.delete_layer("wacom_cursor")
-# Draw a crosshair
+reqapi.clear_layer("wacom_cursor")
+# Draw a crosshair which stays the same size however far we zoom
 api.draw_line(
     "wacom_cursor",
-    -CURSOR_SIZE, 0.0, CURSOR_SIZE, 0.0, 0.001,
+    -CURSOR_SIZE, 0.0, CURSOR_SIZE, 0.0, 1.0,
     *CURSOR_COLOR
 )
 api.draw_line(
     "wacom_cursor",
-    0.0, -CURSOR_SIZE, 0.0, CURSOR_SIZE, 0.001,
+    0.0, -CURSOR_SIZE, 0.0, CURSOR_SIZE, 1.0,
     *CURSOR_COLOR
 )
+# The crosshair layer only exists once the lines above arrive
+while reqapi.set_layer_screen_space("wacom_cursor") == Status.FAILED:
+    time.sleep(0.01)
+screen_w, screen_h = reqapi.screen_size()
 
 while li.wait() > -1:
     events = li.poll()
@@ -56,8 +62,8 @@ while li.wait() > -1:
                 reqapi.show_layer("wacom_cursor")
         # cursor move
         elif event.type == 1:
-            x, y = event.x, event.y
-            x, y = reqapi.screen_to_world(x, y)
+            screen_x, screen_y = event.x, event.y
+            x, y = reqapi.screen_to_world(screen_x, screen_y)
             #print(x, y)
             if MOUSE_STATE == 1:
                 if current_pos is None:
@@ -72,5 +78,6 @@ while li.wait() > -1:
                 )
                 current_pos = (x, y)
             else:
-                reqapi.set_layer_pos("wacom_cursor", x, y)
+                reqapi.set_layer_pos(
+                    "wacom_cursor", screen_x * screen_w, screen_y * screen_h)
 
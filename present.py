@@ -0,0 +1,31 @@
+#!/usr/bin/python
+# Presentation mode. PageDown and PageUp step through the slides.
+# Pass the address of the presenter's canvas to follow their slides instead.
+import sys
+from pytherapy import ReqApi, Event, Notifier
+
+reqapi = ReqApi()
+reqapi.set_slide_clipping(True)
+
+if len(sys.argv) > 1:
+    # Follow along with a remote presenter
+    presenter = Notifier(addr=sys.argv[1])
+    for ev in presenter:
+        if ev.type != Event.SLIDE_CHANGED:
+            continue
+        print(f"Slide {ev.index + 1}/{ev.count}: {ev.name}")
+        reqapi.goto_slide(ev.name)
+
+notify = Notifier()
+for ev in notify:
+    if ev.type == Event.SLIDE_CHANGED:
+        print(f"Slide {ev.index + 1}/{ev.count}: {ev.name}")
+    if ev.type != Event.KEY_DOWN or ev.repeat:
+        continue
+    match ev.key:
+        case "PageDown":
+            reqapi.next_slide()
+        case "PageUp":
+            reqapi.prev_slide()
+        case "Escape":
+            reqapi.set_slide_clipping(False)
@@ -1,3 +1,3 @@
-from .api import PushApi, ReqApi
+from .api import PushApi, ReqApi, LayerInfo, Background, Frame, NetStats, Status, RequestRejected
 from .eventsub import Notifier, Event, MouseButton
 
@@ -1,4 +1,5 @@
 import zmq
+from collections import namedtuple
 from . import serial
 
 COMMAND_HELLO = 0
@@ -12,6 +13,73 @@ COMMAND_SHOWLAYER = 7
 COMMAND_HIDELAYER = 8
 COMMAND_SETLAYERPOS = 9
 COMMAND_SCREENSIZE = 10
+COMMAND_SETLAYEROPACITY = 11
+COMMAND_LOCKLAYER = 12
+COMMAND_UNLOCKLAYER = 13
+COMMAND_RENAMELAYER = 14
+COMMAND_CLONELAYER = 15
+COMMAND_MERGELAYERS = 16
+COMMAND_CLEARLAYER = 17
+COMMAND_GETLAYERINFO = 18
+COMMAND_SETLAYERMETADATA = 19
+COMMAND_GETVIEW = 20
+COMMAND_SETVIEW = 21
+COMMAND_WORLDTOSCREEN = 22
+COMMAND_ZOOMAT = 23
+COMMAND_ZOOMATSCREEN = 24
+COMMAND_ROTATE = 25
+COMMAND_ROTATEAT = 26
+COMMAND_FITALL = 27
+COMMAND_FITLAYER = 28
+COMMAND_SETBACKGROUND = 29
+COMMAND_SETLAYERSCREENSPACE = 30
+COMMAND_SETMINIMAP = 31
+COMMAND_SAVEBOOKMARK = 32
+COMMAND_GOTOBOOKMARK = 33
+COMMAND_LISTBOOKMARKS = 34
+COMMAND_DELETEBOOKMARK = 35
+COMMAND_SETFRAME = 36
+COMMAND_DELETEFRAME = 37
+COMMAND_LISTFRAMES = 38
+COMMAND_GOTOSLIDE = 39
+COMMAND_NEXTSLIDE = 40
+COMMAND_PREVSLIDE = 41
+COMMAND_SETSLIDECLIPPING = 42
+COMMAND_REMOTEVIEW = 43
+COMMAND_FOLLOWVIEW = 44
+COMMAND_SETVIEWPORT = 45
+COMMAND_DELETEVIEWPORT = 46
+COMMAND_SETVIEWPORTVIEW = 47
+COMMAND_SETVIEWPORTLAYERS = 48
+COMMAND_GETNETSTATS = 49
+COMMAND_SETANTIALIASING = 50
+COMMAND_SETMINSTROKEWIDTH = 51
+
+# Replies to commands which edit layers
+class Status:
+    # No such layer, or the edit doesn't make sense
+    FAILED = 0
+    OK = 1
+    # The layer, a group it's in or something inside it is locked
+    LOCKED = 2
+
+class Background:
+    PLAIN = 0
+    GRID = 1
+    DOTS = 2
+    RULED = 3
+    ISOMETRIC = 4
+
+LayerInfo = namedtuple("LayerInfo", [
+    "is_hidden", "is_visible", "opacity", "model",
+    "verts", "faces", "strokes", "bounds",
+    "is_locked", "owner", "metadata", "is_screen_space"
+])
+
+Frame = namedtuple("Frame", ["name", "bounds", "order"])
+NetStats = namedtuple("NetStats", [
+    "received", "stalls", "queued", "max_queued", "deferred_frames"
+])
 
 class PushApi:
 
@@ -53,6 +121,83 @@ class PushApi:
         serial.write_f32(req, scale)
         _ = self._push_cmd(COMMAND_ZOOM, req)
 
+    # Zoom keeping the world point (x, y) fixed on the screen
+    def zoom_at(self, scale, x, y):
+        req = bytearray()
+        serial.write_f32(req, scale)
+        serial.write_f32(req, x)
+        serial.write_f32(req, y)
+        _ = self._push_cmd(COMMAND_ZOOMAT, req)
+
+    # Same as zoom_at() but (x, y) is a screen position between (0, 1)
+    def zoom_at_screen(self, scale, x, y):
+        req = bytearray()
+        serial.write_f32(req, scale)
+        serial.write_f32(req, x)
+        serial.write_f32(req, y)
+        _ = self._push_cmd(COMMAND_ZOOMATSCREEN, req)
+
+    # Rotate the board counter-clockwise around the centre of the screen.
+    # The angle is in radians.
+    def rotate(self, angle):
+        req = bytearray()
+        serial.write_f32(req, angle)
+        _ = self._push_cmd(COMMAND_ROTATE, req)
+
+    # Rotate keeping the world point (x, y) fixed on the screen
+    def rotate_at(self, angle, x, y):
+        req = bytearray()
+        serial.write_f32(req, angle)
+        serial.write_f32(req, x)
+        serial.write_f32(req, y)
+        _ = self._push_cmd(COMMAND_ROTATEAT, req)
+
+    # Centre of the view in world coords, zoom and rotation in radians.
+    # The camera glides there over duration seconds, at most 10.
+    def set_view(self, x, y, zoom, rotation, duration=0.):
+        req = bytearray()
+        serial.write_f32(req, x)
+        serial.write_f32(req, y)
+        serial.write_f32(req, zoom)
+        serial.write_f32(req, rotation)
+        serial.write_f32(req, duration)
+        _ = self._push_cmd(COMMAND_SETVIEW, req)
+
+    # Overview of the whole board in the bottom right corner.
+    # Clicking it moves the view and sends Event.MINIMAP_CLICK.
+    def set_minimap(self, is_visible=True):
+        req = bytearray()
+        serial.write_u8(req, int(is_visible))
+        _ = self._push_cmd(COMMAND_SETMINIMAP, req)
+
+    # Forward a peer's view, see follow.py. It's ignored unless we're
+    # following that peer with ReqApi.follow_view().
+    def remote_view(self, peer, x, y, zoom, rotation):
+        req = bytearray()
+        serial.encode_str(req, peer)
+        serial.write_f32(req, x)
+        serial.write_f32(req, y)
+        serial.write_f32(req, zoom)
+        serial.write_f32(req, rotation)
+        _ = self._push_cmd(COMMAND_REMOTEVIEW, req)
+
+    # Smooth stroke edges. On by default.
+    def set_antialiasing(self, is_enabled=True):
+        req = bytearray()
+        serial.write_u8(req, int(is_enabled))
+        _ = self._push_cmd(COMMAND_SETANTIALIASING, req)
+
+    # Thinner strokes get drawn this many pixels wide so they don't vanish
+    # when zoomed out. Defaults to 1, use 0 to draw them at their real width.
+    def set_min_stroke_width(self, pixels=1.):
+        req = bytearray()
+        serial.write_f32(req, pixels)
+        _ = self._push_cmd(COMMAND_SETMINSTROKEWIDTH, req)
+
+# The canvas couldn't decode the request, so it replied with nothing
+class RequestRejected(Exception):
+    pass
+
 class ReqApi:
 
     def __init__(self, addr="[::1]", port=9464):
@@ -67,6 +212,8 @@ class ReqApi:
         self.socket.send_multipart([req_cmd, payload])
 
         reply = self.socket.recv()
+        if not reply:
+            raise RequestRejected(f"command {cmd} rejected, see the canvas log")
         cursor = serial.Cursor(reply)
         return cursor
 
@@ -91,12 +238,12 @@ class ReqApi:
             layers.append(serial.decode_str(cur))
         return layers
 
+    # Commands which edit layers return a Status rather than a bool
     def delete_layer(self, layer_name):
         req = bytearray()
         serial.encode_str(req, layer_name)
         cur = self._make_request(COMMAND_DELETELAYER, req)
-        is_success = serial.read_u8(cur)
-        return bool(is_success)
+        return serial.read_u8(cur)
 
     def show_layer(self, layer_name):
         req = bytearray()
@@ -118,8 +265,7 @@ class ReqApi:
         serial.write_f32(req, x)
         serial.write_f32(req, y)
         cur = self._make_request(COMMAND_SETLAYERPOS, req)
-        is_success = serial.read_u8(cur)
-        return bool(is_success)
+        return serial.read_u8(cur)
 
     def screen_size(self):
         cur = self._make_request(COMMAND_SCREENSIZE, bytearray())
@@ -127,3 +273,315 @@ class ReqApi:
         h = serial.read_f32(cur)
         return (w, h)
 
+    def set_layer_opacity(self, layer_name, opacity):
+        req = bytearray()
+        serial.encode_str(req, layer_name)
+        serial.write_f32(req, opacity)
+        cur = self._make_request(COMMAND_SETLAYEROPACITY, req)
+        return serial.read_u8(cur)
+
+    def lock_layer(self, layer_name, owner):
+        req = bytearray()
+        serial.encode_str(req, layer_name)
+        serial.encode_str(req, owner)
+        cur = self._make_request(COMMAND_LOCKLAYER, req)
+        return serial.read_u8(cur)
+
+    def unlock_layer(self, layer_name, owner):
+        req = bytearray()
+        serial.encode_str(req, layer_name)
+        serial.encode_str(req, owner)
+        cur = self._make_request(COMMAND_UNLOCKLAYER, req)
+        return serial.read_u8(cur)
+
+    def rename_layer(self, old_name, new_name):
+        req = bytearray()
+        serial.encode_str(req, old_name)
+        serial.encode_str(req, new_name)
+        cur = self._make_request(COMMAND_RENAMELAYER, req)
+        return serial.read_u8(cur)
+
+    def clone_layer(self, src_name, dst_name):
+        req = bytearray()
+        serial.encode_str(req, src_name)
+        serial.encode_str(req, dst_name)
+        cur = self._make_request(COMMAND_CLONELAYER, req)
+        return serial.read_u8(cur)
+
+    def merge_layers(self, src_names, dst_name):
+        req = bytearray()
+        serial.encode_varint(req, len(src_names))
+        for src_name in src_names:
+            serial.encode_str(req, src_name)
+        serial.encode_str(req, dst_name)
+        cur = self._make_request(COMMAND_MERGELAYERS, req)
+        return serial.read_u8(cur)
+
+    def clear_layer(self, layer_name):
+        req = bytearray()
+        serial.encode_str(req, layer_name)
+        cur = self._make_request(COMMAND_CLEARLAYER, req)
+        return serial.read_u8(cur)
+
+    def get_layer_info(self, layer_name):
+        req = bytearray()
+        serial.encode_str(req, layer_name)
+        cur = self._make_request(COMMAND_GETLAYERINFO, req)
+        is_success = serial.read_u8(cur)
+        if not is_success:
+            return None
+        is_hidden = bool(serial.read_u8(cur))
+        is_visible = bool(serial.read_u8(cur))
+        opacity = serial.read_f32(cur)
+        # 4x4 matrix in column major order
+        model = [serial.read_f32(cur) for _ in range(16)]
+        verts = serial.read_u64(cur)
+        faces = serial.read_u64(cur)
+        strokes = serial.read_u64(cur)
+        # (min_x, min_y, max_x, max_y) in world space, covering everything
+        # inside the layer too. None if empty or a screen space overlay.
+        bounds = None
+        if serial.read_u8(cur):
+            bounds = tuple(serial.read_f32(cur) for _ in range(4))
+        is_locked = bool(serial.read_u8(cur))
+        owner = serial.decode_str(cur) or None
+        metadata = {}
+        for _ in range(serial.decode_varint(cur)):
+            key = serial.decode_str(cur)
+            metadata[key] = serial.decode_str(cur)
+        is_screen_space = bool(serial.read_u8(cur))
+        return LayerInfo(
+            is_hidden, is_visible, opacity, model,
+            verts, faces, strokes, bounds,
+            is_locked, owner, metadata, is_screen_space
+        )
+
+    def set_layer_metadata(self, layer_name, key, value):
+        req = bytearray()
+        serial.encode_str(req, layer_name)
+        serial.encode_str(req, key)
+        serial.encode_str(req, value)
+        cur = self._make_request(COMMAND_SETLAYERMETADATA, req)
+        return serial.read_u8(cur)
+
+    # Show everything that's visible. Returns False if the board is empty.
+    # Duration is how many seconds the camera takes to get there.
+    def fit_all(self, duration=0.):
+        req = bytearray()
+        serial.write_f32(req, duration)
+        cur = self._make_request(COMMAND_FITALL, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    # Show a layer and everything inside it. Padding is the fraction of the
+    # screen to leave empty around each side.
+    def fit_layer(self, layer_name, padding=0.05, duration=0.):
+        req = bytearray()
+        serial.encode_str(req, layer_name)
+        serial.write_f32(req, padding)
+        serial.write_f32(req, duration)
+        cur = self._make_request(COMMAND_FITLAYER, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    # Spacing is in world units. It gets coarser or finer as you zoom so the
+    # pattern never gets too dense.
+    def set_background(self, mode, color=(0., 0., 0., 1.),
+                       line_color=(1., 1., 1., 0.15), spacing=0.02):
+        req = bytearray()
+        serial.write_u8(req, mode)
+        for val in color:
+            serial.write_f32(req, val)
+        for val in line_color:
+            serial.write_f32(req, val)
+        serial.write_f32(req, spacing)
+        cur = self._make_request(COMMAND_SETBACKGROUND, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    # Returns (x, y, zoom, rotation), see PushApi.set_view()
+    def get_view(self):
+        cur = self._make_request(COMMAND_GETVIEW, bytearray())
+        x = serial.read_f32(cur)
+        y = serial.read_f32(cur)
+        zoom = serial.read_f32(cur)
+        rotation = serial.read_f32(cur)
+        return (x, y, zoom, rotation)
+
+    def world_to_screen(self, x, y):
+        req = bytearray()
+        serial.write_f32(req, x)
+        serial.write_f32(req, y)
+        cur = self._make_request(COMMAND_WORLDTOSCREEN, req)
+        x = serial.read_f32(cur)
+        y = serial.read_f32(cur)
+        return (x, y)
+
+    # Screen space layers are drawn on top of everything in screen pixels,
+    # with (0, 0) at the top left. They don't move with the camera.
+    def set_layer_screen_space(self, layer_name, is_screen_space=True):
+        req = bytearray()
+        serial.encode_str(req, layer_name)
+        serial.write_u8(req, int(is_screen_space))
+        cur = self._make_request(COMMAND_SETLAYERSCREENSPACE, req)
+        return serial.read_u8(cur)
+
+    # Remember the current view under name, replacing any older bookmark
+    def save_bookmark(self, name):
+        req = bytearray()
+        serial.encode_str(req, name)
+        cur = self._make_request(COMMAND_SAVEBOOKMARK, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    # Glide back to a saved view over duration seconds
+    def goto_bookmark(self, name, duration=0.):
+        req = bytearray()
+        serial.encode_str(req, name)
+        serial.write_f32(req, duration)
+        cur = self._make_request(COMMAND_GOTOBOOKMARK, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    # Returns {name: (x, y, zoom, rotation)}, see get_view()
+    def list_bookmarks(self):
+        cur = self._make_request(COMMAND_LISTBOOKMARKS, bytearray())
+        bookmarks_len = serial.decode_varint(cur)
+        bookmarks = {}
+        for _ in range(bookmarks_len):
+            name = serial.decode_str(cur)
+            x = serial.read_f32(cur)
+            y = serial.read_f32(cur)
+            zoom = serial.read_f32(cur)
+            rotation = serial.read_f32(cur)
+            bookmarks[name] = (x, y, zoom, rotation)
+        return bookmarks
+
+    def delete_bookmark(self, name):
+        req = bytearray()
+        serial.encode_str(req, name)
+        cur = self._make_request(COMMAND_DELETEBOOKMARK, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    # A slide covering the world rectangle between (x1, y1) and (x2, y2).
+    # Slides are presented by order, then by name.
+    def set_frame(self, name, x1, y1, x2, y2, order=0):
+        req = bytearray()
+        serial.encode_str(req, name)
+        serial.write_f32(req, x1)
+        serial.write_f32(req, y1)
+        serial.write_f32(req, x2)
+        serial.write_f32(req, y2)
+        serial.write_u32(req, order)
+        cur = self._make_request(COMMAND_SETFRAME, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    def delete_frame(self, name):
+        req = bytearray()
+        serial.encode_str(req, name)
+        cur = self._make_request(COMMAND_DELETEFRAME, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    # Returns a list of Frame in presentation order
+    def list_frames(self):
+        cur = self._make_request(COMMAND_LISTFRAMES, bytearray())
+        frames_len = serial.decode_varint(cur)
+        frames = []
+        for _ in range(frames_len):
+            name = serial.decode_str(cur)
+            bounds = [serial.read_f32(cur) for _ in range(4)]
+            order = serial.read_u32(cur)
+            frames.append(Frame(name, tuple(bounds), order))
+        return frames
+
+    def goto_slide(self, name, duration=0.5):
+        req = bytearray()
+        serial.encode_str(req, name)
+        serial.write_f32(req, duration)
+        cur = self._make_request(COMMAND_GOTOSLIDE, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    # Returns False when already on the last slide
+    def next_slide(self, duration=0.5):
+        req = bytearray()
+        serial.write_f32(req, duration)
+        cur = self._make_request(COMMAND_NEXTSLIDE, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    # Returns False when already on the first slide
+    def prev_slide(self, duration=0.5):
+        req = bytearray()
+        serial.write_f32(req, duration)
+        cur = self._make_request(COMMAND_PREVSLIDE, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    # Hide everything outside the current slide
+    def set_slide_clipping(self, is_clipping=True):
+        req = bytearray()
+        serial.write_u8(req, int(is_clipping))
+        cur = self._make_request(COMMAND_SETSLIDECLIPPING, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    # Let remote views from peer drive the camera. An empty peer stops
+    # following, and so does moving the view by hand.
+    def follow_view(self, peer):
+        req = bytearray()
+        serial.encode_str(req, peer)
+        cur = self._make_request(COMMAND_FOLLOWVIEW, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    # An extra view over part of the window, between screen coords (x1, y1)
+    # and (x2, y2) in (0, 1). New viewports start with the main view.
+    def set_viewport(self, name, x1, y1, x2, y2):
+        req = bytearray()
+        serial.encode_str(req, name)
+        serial.write_f32(req, x1)
+        serial.write_f32(req, y1)
+        serial.write_f32(req, x2)
+        serial.write_f32(req, y2)
+        cur = self._make_request(COMMAND_SETVIEWPORT, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    def delete_viewport(self, name):
+        req = bytearray()
+        serial.encode_str(req, name)
+        cur = self._make_request(COMMAND_DELETEVIEWPORT, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    # Same as PushApi.set_view() but for a viewport
+    def set_viewport_view(self, name, x, y, zoom, rotation):
+        req = bytearray()
+        serial.encode_str(req, name)
+        serial.write_f32(req, x)
+        serial.write_f32(req, y)
+        serial.write_f32(req, zoom)
+        serial.write_f32(req, rotation)
+        cur = self._make_request(COMMAND_SETVIEWPORTVIEW, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    # Only draw these layers and whatever is inside them. An empty list
+    # draws everything.
+    def set_viewport_layers(self, name, layer_names):
+        req = bytearray()
+        serial.encode_str(req, name)
+        serial.encode_varint(req, len(layer_names))
+        for layer_name in layer_names:
+            serial.encode_str(req, layer_name)
+        cur = self._make_request(COMMAND_SETVIEWPORTLAYERS, req)
+        is_success = serial.read_u8(cur)
+        return bool(is_success)
+
+    def get_net_stats(self):
+        cur = self._make_request(COMMAND_GETNETSTATS, bytearray())
+        return NetStats(*(serial.read_u64(cur) for _ in range(5)))
@@ -8,6 +8,10 @@ class Event:
     MOUSE_WHEEL = 2
     MOUSE_BUTTON_DOWN = 3
     MOUSE_BUTTON_UP = 4
+    MINIMAP_CLICK = 5
+    SLIDE_CHANGED = 6
+    VIEW_CHANGED = 7
+    LAYER_LOCKED = 8
 
 class MouseButton:
     LEFT = 0
@@ -20,12 +24,17 @@ EventMouseMotion = namedtuple("EventMouseMotion", ["type", "x", "y"])
 EventMouseWheel = namedtuple("EventKeyDown", ["type", "x", "y"])
 EventMouseButtonDown = namedtuple("EventMouseButtonDown", ["type", "button", "x", "y"])
 EventMouseButtonUp = namedtuple("EventMouseButtonUp", ["type", "button", "x", "y"])
+EventMinimapClick = namedtuple("EventMinimapClick", ["type", "button", "x", "y"])
+EventSlideChanged = namedtuple("EventSlideChanged", ["type", "name", "index", "count"])
+EventViewChanged = namedtuple("EventViewChanged", ["type", "x", "y", "zoom", "rotation"])
+EventLayerLocked = namedtuple("EventLayerLocked", ["type", "layer_name"])
 
 class Notifier:
-    def __init__(self, port=9465):
+    def __init__(self, addr="localhost", port=9465):
         context = zmq.Context()
         self.socket = context.socket(zmq.SUB)
-        self.socket.connect(f"tcp://localhost:{port}")
+        self.socket.setsockopt(zmq.IPV6, True)
+        self.socket.connect(f"tcp://{addr}:{port}")
         self.socket.setsockopt(zmq.SUBSCRIBE, b'')
 
     def set_filter(self, ev):
@@ -66,4 +75,22 @@ class Notifier:
                 x = serial.read_f32(cursor)
                 y = serial.read_f32(cursor)
                 return EventMouseButtonUp(ev_type, button, x, y)
-
+            case Event.MINIMAP_CLICK:
+                button = serial.read_u8(cursor)
+                x = serial.read_f32(cursor)
+                y = serial.read_f32(cursor)
+                return EventMinimapClick(ev_type, button, x, y)
+            case Event.SLIDE_CHANGED:
+                name = serial.decode_str(cursor)
+                index = serial.read_u32(cursor)
+                count = serial.read_u32(cursor)
+                return EventSlideChanged(ev_type, name, index, count)
+            case Event.VIEW_CHANGED:
+                x = serial.read_f32(cursor)
+                y = serial.read_f32(cursor)
+                zoom = serial.read_f32(cursor)
+                rotation = serial.read_f32(cursor)
+                return EventViewChanged(ev_type, x, y, zoom, rotation)
+            case Event.LAYER_LOCKED:
+                layer_name = serial.decode_str(cursor)
+                return EventLayerLocked(ev_type, layer_name)
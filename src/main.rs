@@ -1,9 +1,20 @@
 #![feature(stmt_expr_attributes)]
 
-use darkfi_serial::{deserialize, Decodable, Encodable, SerialDecodable};
+use darkfi_serial::{Decodable, Encodable, SerialDecodable, VarInt};
 use glam::Vec4Swizzles;
 use miniquad::*;
-use std::{collections::HashMap, fmt, io::Cursor, time};
+use std::{
+    cell::OnceCell,
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    io::Cursor,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    thread, time,
+};
 
 #[macro_use]
 extern crate log;
@@ -23,24 +34,106 @@ enum Command {
     HideLayer = 8,
     SetLayerPos = 9,
     ScreenSize = 10,
+    SetLayerOpacity = 11,
+    LockLayer = 12,
+    UnlockLayer = 13,
+    RenameLayer = 14,
+    CloneLayer = 15,
+    MergeLayers = 16,
+    ClearLayer = 17,
+    GetLayerInfo = 18,
+    SetLayerMetadata = 19,
+    GetView = 20,
+    SetView = 21,
+    WorldToScreen = 22,
+    ZoomAt = 23,
+    ZoomAtScreen = 24,
+    Rotate = 25,
+    RotateAt = 26,
+    FitAll = 27,
+    FitLayer = 28,
+    SetBackground = 29,
+    SetLayerScreenSpace = 30,
+    SetMinimap = 31,
+    SaveBookmark = 32,
+    GotoBookmark = 33,
+    ListBookmarks = 34,
+    DeleteBookmark = 35,
+    SetFrame = 36,
+    DeleteFrame = 37,
+    ListFrames = 38,
+    GotoSlide = 39,
+    NextSlide = 40,
+    PrevSlide = 41,
+    SetSlideClipping = 42,
+    RemoteView = 43,
+    FollowView = 44,
+    SetViewport = 45,
+    DeleteViewport = 46,
+    SetViewportView = 47,
+    SetViewportLayers = 48,
+    GetNetStats = 49,
+    SetAntialiasing = 50,
+    SetMinStrokeWidth = 51,
 }
 
 impl Command {
     // Ridiculous
-    fn from_u8(cmd: u8) -> Self {
+    fn from_u8(cmd: u8) -> Option<Self> {
         match cmd {
-            0 => Command::Hello,
-            1 => Command::DrawLine,
-            2 => Command::Pan,
-            3 => Command::Zoom,
-            4 => Command::ScreenToWorld,
-            5 => Command::GetLayers,
-            6 => Command::DeleteLayer,
-            7 => Command::ShowLayer,
-            8 => Command::HideLayer,
-            9 => Command::SetLayerPos,
-            10 => Command::ScreenSize,
-            _ => panic!("invalid cmd"),
+            0 => Some(Command::Hello),
+            1 => Some(Command::DrawLine),
+            2 => Some(Command::Pan),
+            3 => Some(Command::Zoom),
+            4 => Some(Command::ScreenToWorld),
+            5 => Some(Command::GetLayers),
+            6 => Some(Command::DeleteLayer),
+            7 => Some(Command::ShowLayer),
+            8 => Some(Command::HideLayer),
+            9 => Some(Command::SetLayerPos),
+            10 => Some(Command::ScreenSize),
+            11 => Some(Command::SetLayerOpacity),
+            12 => Some(Command::LockLayer),
+            13 => Some(Command::UnlockLayer),
+            14 => Some(Command::RenameLayer),
+            15 => Some(Command::CloneLayer),
+            16 => Some(Command::MergeLayers),
+            17 => Some(Command::ClearLayer),
+            18 => Some(Command::GetLayerInfo),
+            19 => Some(Command::SetLayerMetadata),
+            20 => Some(Command::GetView),
+            21 => Some(Command::SetView),
+            22 => Some(Command::WorldToScreen),
+            23 => Some(Command::ZoomAt),
+            24 => Some(Command::ZoomAtScreen),
+            25 => Some(Command::Rotate),
+            26 => Some(Command::RotateAt),
+            27 => Some(Command::FitAll),
+            28 => Some(Command::FitLayer),
+            29 => Some(Command::SetBackground),
+            30 => Some(Command::SetLayerScreenSpace),
+            31 => Some(Command::SetMinimap),
+            32 => Some(Command::SaveBookmark),
+            33 => Some(Command::GotoBookmark),
+            34 => Some(Command::ListBookmarks),
+            35 => Some(Command::DeleteBookmark),
+            36 => Some(Command::SetFrame),
+            37 => Some(Command::DeleteFrame),
+            38 => Some(Command::ListFrames),
+            39 => Some(Command::GotoSlide),
+            40 => Some(Command::NextSlide),
+            41 => Some(Command::PrevSlide),
+            42 => Some(Command::SetSlideClipping),
+            43 => Some(Command::RemoteView),
+            44 => Some(Command::FollowView),
+            45 => Some(Command::SetViewport),
+            46 => Some(Command::DeleteViewport),
+            47 => Some(Command::SetViewportView),
+            48 => Some(Command::SetViewportLayers),
+            49 => Some(Command::GetNetStats),
+            50 => Some(Command::SetAntialiasing),
+            51 => Some(Command::SetMinStrokeWidth),
+            _ => None,
         }
     }
 }
@@ -54,6 +147,444 @@ struct RequestDrawLine {
     r: f32, g: f32, b: f32, a: f32
 }
 
+#[derive(Debug, SerialDecodable)]
+#[rustfmt::skip]
+struct RequestSetBackground {
+    mode: u8,
+    r: f32, g: f32, b: f32, a: f32,
+    line_r: f32, line_g: f32, line_b: f32, line_a: f32,
+    spacing: f32,
+}
+
+#[derive(Debug, SerialDecodable)]
+#[rustfmt::skip]
+struct RequestSetFrame {
+    name: String,
+    x1: f32, y1: f32, x2: f32, y2: f32,
+    order: u32,
+}
+
+#[derive(Debug, SerialDecodable)]
+#[rustfmt::skip]
+struct RequestRemoteView {
+    peer: String,
+    x: f32, y: f32,
+    zoom: f32,
+    rotation: f32,
+}
+
+#[derive(Debug, SerialDecodable)]
+#[rustfmt::skip]
+struct RequestSetViewport {
+    name: String,
+    x1: f32, y1: f32, x2: f32, y2: f32,
+}
+
+#[derive(Debug, SerialDecodable)]
+#[rustfmt::skip]
+struct RequestSetViewportView {
+    name: String,
+    x: f32, y: f32,
+    zoom: f32,
+    rotation: f32,
+}
+
+fn invalid_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+// Leftover bytes mean the client and the canvas disagree on the format
+fn ensure_consumed(cur: &Cursor<&[u8]>) -> std::io::Result<()> {
+    if cur.position() as usize == cur.get_ref().len() {
+        Ok(())
+    } else {
+        Err(invalid_data("trailing bytes after payload"))
+    }
+}
+
+// Commands on the req socket, which each get a reply. The network thread
+// decodes these so bad input never reaches the main thread.
+#[derive(Debug)]
+enum ReqCommand {
+    Hello,
+    ScreenToWorld {
+        x: f32,
+        y: f32,
+    },
+    GetLayers,
+    DeleteLayer {
+        layer_name: String,
+    },
+    ShowLayer {
+        layer_name: String,
+    },
+    HideLayer {
+        layer_name: String,
+    },
+    SetLayerPos {
+        layer_name: String,
+        x: f32,
+        y: f32,
+    },
+    ScreenSize,
+    SetLayerOpacity {
+        layer_name: String,
+        opacity: f32,
+    },
+    LockLayer {
+        layer_name: String,
+        owner: String,
+    },
+    UnlockLayer {
+        layer_name: String,
+        owner: String,
+    },
+    RenameLayer {
+        old_name: String,
+        new_name: String,
+    },
+    CloneLayer {
+        src_name: String,
+        dst_name: String,
+    },
+    MergeLayers {
+        src_names: Vec<String>,
+        dst_name: String,
+    },
+    ClearLayer {
+        layer_name: String,
+    },
+    GetLayerInfo {
+        layer_name: String,
+    },
+    SetLayerMetadata {
+        layer_name: String,
+        key: String,
+        value: String,
+    },
+    GetView,
+    WorldToScreen {
+        x: f32,
+        y: f32,
+    },
+    FitAll {
+        duration: f32,
+    },
+    FitLayer {
+        layer_name: String,
+        padding: f32,
+        duration: f32,
+    },
+    SetBackground(RequestSetBackground),
+    SetLayerScreenSpace {
+        layer_name: String,
+        is_screen_space: bool,
+    },
+    SaveBookmark {
+        name: String,
+    },
+    GotoBookmark {
+        name: String,
+        duration: f32,
+    },
+    ListBookmarks,
+    DeleteBookmark {
+        name: String,
+    },
+    SetFrame(RequestSetFrame),
+    DeleteFrame {
+        name: String,
+    },
+    ListFrames,
+    GotoSlide {
+        name: String,
+        duration: f32,
+    },
+    NextSlide {
+        duration: f32,
+    },
+    PrevSlide {
+        duration: f32,
+    },
+    SetSlideClipping {
+        is_clipping: bool,
+    },
+    FollowView {
+        peer: String,
+    },
+    SetViewport(RequestSetViewport),
+    DeleteViewport {
+        name: String,
+    },
+    SetViewportView(RequestSetViewportView),
+    SetViewportLayers {
+        name: String,
+        layer_filter: Vec<String>,
+    },
+    GetNetStats,
+}
+
+impl ReqCommand {
+    fn decode(cmd: Command, payload: &[u8]) -> std::io::Result<Self> {
+        let mut cur = Cursor::new(payload);
+        let req = match cmd {
+            Command::Hello => Self::Hello,
+            Command::ScreenToWorld => Self::ScreenToWorld {
+                x: f32::decode(&mut cur)?,
+                y: f32::decode(&mut cur)?,
+            },
+            Command::GetLayers => Self::GetLayers,
+            Command::DeleteLayer => Self::DeleteLayer {
+                layer_name: String::decode(&mut cur)?,
+            },
+            Command::ShowLayer => Self::ShowLayer {
+                layer_name: String::decode(&mut cur)?,
+            },
+            Command::HideLayer => Self::HideLayer {
+                layer_name: String::decode(&mut cur)?,
+            },
+            Command::SetLayerPos => Self::SetLayerPos {
+                layer_name: String::decode(&mut cur)?,
+                x: f32::decode(&mut cur)?,
+                y: f32::decode(&mut cur)?,
+            },
+            Command::ScreenSize => Self::ScreenSize,
+            Command::SetLayerOpacity => Self::SetLayerOpacity {
+                layer_name: String::decode(&mut cur)?,
+                opacity: f32::decode(&mut cur)?,
+            },
+            Command::LockLayer => Self::LockLayer {
+                layer_name: String::decode(&mut cur)?,
+                owner: String::decode(&mut cur)?,
+            },
+            Command::UnlockLayer => Self::UnlockLayer {
+                layer_name: String::decode(&mut cur)?,
+                owner: String::decode(&mut cur)?,
+            },
+            Command::RenameLayer => Self::RenameLayer {
+                old_name: String::decode(&mut cur)?,
+                new_name: String::decode(&mut cur)?,
+            },
+            Command::CloneLayer => Self::CloneLayer {
+                src_name: String::decode(&mut cur)?,
+                dst_name: String::decode(&mut cur)?,
+            },
+            Command::MergeLayers => Self::MergeLayers {
+                src_names: Vec::<String>::decode(&mut cur)?,
+                dst_name: String::decode(&mut cur)?,
+            },
+            Command::ClearLayer => Self::ClearLayer {
+                layer_name: String::decode(&mut cur)?,
+            },
+            Command::GetLayerInfo => Self::GetLayerInfo {
+                layer_name: String::decode(&mut cur)?,
+            },
+            Command::SetLayerMetadata => Self::SetLayerMetadata {
+                layer_name: String::decode(&mut cur)?,
+                key: String::decode(&mut cur)?,
+                value: String::decode(&mut cur)?,
+            },
+            Command::GetView => Self::GetView,
+            Command::WorldToScreen => Self::WorldToScreen {
+                x: f32::decode(&mut cur)?,
+                y: f32::decode(&mut cur)?,
+            },
+            Command::FitAll => Self::FitAll {
+                duration: f32::decode(&mut cur)?,
+            },
+            Command::FitLayer => Self::FitLayer {
+                layer_name: String::decode(&mut cur)?,
+                padding: f32::decode(&mut cur)?,
+                duration: f32::decode(&mut cur)?,
+            },
+            Command::SetBackground => Self::SetBackground(RequestSetBackground::decode(&mut cur)?),
+            Command::SetLayerScreenSpace => Self::SetLayerScreenSpace {
+                layer_name: String::decode(&mut cur)?,
+                is_screen_space: bool::decode(&mut cur)?,
+            },
+            Command::SaveBookmark => Self::SaveBookmark {
+                name: String::decode(&mut cur)?,
+            },
+            Command::GotoBookmark => Self::GotoBookmark {
+                name: String::decode(&mut cur)?,
+                duration: f32::decode(&mut cur)?,
+            },
+            Command::ListBookmarks => Self::ListBookmarks,
+            Command::DeleteBookmark => Self::DeleteBookmark {
+                name: String::decode(&mut cur)?,
+            },
+            Command::SetFrame => Self::SetFrame(RequestSetFrame::decode(&mut cur)?),
+            Command::DeleteFrame => Self::DeleteFrame {
+                name: String::decode(&mut cur)?,
+            },
+            Command::ListFrames => Self::ListFrames,
+            Command::GotoSlide => Self::GotoSlide {
+                name: String::decode(&mut cur)?,
+                duration: f32::decode(&mut cur)?,
+            },
+            Command::NextSlide => Self::NextSlide {
+                duration: f32::decode(&mut cur)?,
+            },
+            Command::PrevSlide => Self::PrevSlide {
+                duration: f32::decode(&mut cur)?,
+            },
+            Command::SetSlideClipping => Self::SetSlideClipping {
+                is_clipping: bool::decode(&mut cur)?,
+            },
+            Command::FollowView => Self::FollowView {
+                peer: String::decode(&mut cur)?,
+            },
+            Command::SetViewport => Self::SetViewport(RequestSetViewport::decode(&mut cur)?),
+            Command::DeleteViewport => Self::DeleteViewport {
+                name: String::decode(&mut cur)?,
+            },
+            Command::SetViewportView => {
+                Self::SetViewportView(RequestSetViewportView::decode(&mut cur)?)
+            }
+            Command::SetViewportLayers => Self::SetViewportLayers {
+                name: String::decode(&mut cur)?,
+                layer_filter: Vec::<String>::decode(&mut cur)?,
+            },
+            Command::GetNetStats => Self::GetNetStats,
+            _ => return Err(invalid_data("command must be sent on the sub socket")),
+        };
+        ensure_consumed(&cur)?;
+        Ok(req)
+    }
+
+    // Queries which never change what's on screen
+    fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Self::Hello
+                | Self::ScreenToWorld { .. }
+                | Self::GetLayers
+                | Self::ScreenSize
+                | Self::GetLayerInfo { .. }
+                | Self::GetView
+                | Self::WorldToScreen { .. }
+                | Self::ListBookmarks
+                | Self::ListFrames
+                | Self::GetNetStats
+        )
+    }
+}
+
+// Commands on the sub socket, which never reply
+#[derive(Debug)]
+enum SubCommand {
+    DrawLine(RequestDrawLine),
+    Pan {
+        x: f32,
+        y: f32,
+    },
+    Zoom {
+        scale: f32,
+    },
+    SetView {
+        x: f32,
+        y: f32,
+        zoom: f32,
+        rotation: f32,
+        duration: f32,
+    },
+    ZoomAt {
+        scale: f32,
+        x: f32,
+        y: f32,
+    },
+    ZoomAtScreen {
+        scale: f32,
+        x: f32,
+        y: f32,
+    },
+    Rotate {
+        angle: f32,
+    },
+    RotateAt {
+        angle: f32,
+        x: f32,
+        y: f32,
+    },
+    RemoteView(RequestRemoteView),
+    SetMinimap {
+        is_visible: bool,
+    },
+    SetAntialiasing {
+        is_enabled: bool,
+    },
+    SetMinStrokeWidth {
+        width: f32,
+    },
+}
+
+impl SubCommand {
+    fn decode(cmd: Command, payload: &[u8]) -> std::io::Result<Self> {
+        let mut cur = Cursor::new(payload);
+        let sub = match cmd {
+            Command::DrawLine => Self::DrawLine(RequestDrawLine::decode(&mut cur)?),
+            Command::Pan => Self::Pan {
+                x: f32::decode(&mut cur)?,
+                y: f32::decode(&mut cur)?,
+            },
+            Command::Zoom => Self::Zoom {
+                scale: f32::decode(&mut cur)?,
+            },
+            Command::SetView => Self::SetView {
+                x: f32::decode(&mut cur)?,
+                y: f32::decode(&mut cur)?,
+                zoom: f32::decode(&mut cur)?,
+                rotation: f32::decode(&mut cur)?,
+                duration: f32::decode(&mut cur)?,
+            },
+            Command::ZoomAt => Self::ZoomAt {
+                scale: f32::decode(&mut cur)?,
+                x: f32::decode(&mut cur)?,
+                y: f32::decode(&mut cur)?,
+            },
+            Command::ZoomAtScreen => Self::ZoomAtScreen {
+                scale: f32::decode(&mut cur)?,
+                x: f32::decode(&mut cur)?,
+                y: f32::decode(&mut cur)?,
+            },
+            Command::Rotate => Self::Rotate {
+                angle: f32::decode(&mut cur)?,
+            },
+            Command::RotateAt => Self::RotateAt {
+                angle: f32::decode(&mut cur)?,
+                x: f32::decode(&mut cur)?,
+                y: f32::decode(&mut cur)?,
+            },
+            Command::RemoteView => Self::RemoteView(RequestRemoteView::decode(&mut cur)?),
+            Command::SetMinimap => Self::SetMinimap {
+                is_visible: bool::decode(&mut cur)?,
+            },
+            Command::SetAntialiasing => Self::SetAntialiasing {
+                is_enabled: bool::decode(&mut cur)?,
+            },
+            Command::SetMinStrokeWidth => Self::SetMinStrokeWidth {
+                width: f32::decode(&mut cur)?,
+            },
+            _ => return Err(invalid_data("command must be sent on the req socket")),
+        };
+        ensure_consumed(&cur)?;
+        Ok(sub)
+    }
+
+    // Moving the camera by hand takes over from any animation
+    fn is_manual_camera_move(&self) -> bool {
+        matches!(
+            self,
+            Self::Pan { .. }
+                | Self::Zoom { .. }
+                | Self::ZoomAt { .. }
+                | Self::ZoomAtScreen { .. }
+                | Self::Rotate { .. }
+                | Self::RotateAt { .. }
+        )
+    }
+}
+
 #[repr(u8)]
 enum PubEvents {
     KeyDown = 0,
@@ -61,6 +592,21 @@ enum PubEvents {
     MouseWheel = 2,
     MouseButtonDown = 3,
     MouseButtonUp = 4,
+    MinimapClick = 5,
+    SlideChanged = 6,
+    ViewChanged = 7,
+    LayerLocked = 8,
+}
+
+// Reply to commands which edit layers, so clients can tell why one failed
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EditStatus {
+    // No such layer, or the edit doesn't make sense
+    Failed = 0,
+    Ok = 1,
+    // The layer, a group it's in or something inside it is locked
+    Locked = 2,
 }
 
 trait MouseButtonAsString {
@@ -78,13 +624,46 @@ impl MouseButtonAsString for MouseButton {
     }
 }
 
+#[derive(Clone)]
 #[repr(C)]
 struct Vertex {
     pos: [f32; 2],
     color: [f32; 4],
     uv: [f32; 2],
+    // Offset from the centre line of the stroke, used to widen thin strokes
+    normal: [f32; 2],
+}
+
+static NEXT_MESH_ID: AtomicU64 = AtomicU64::new(0);
+
+// Names a mesh's copy on the GPU. The version goes up whenever the mesh
+// changes so the renderer knows to upload it again.
+struct MeshTag {
+    id: u64,
+    version: u64,
+}
+
+impl MeshTag {
+    fn new() -> Self {
+        Self {
+            id: NEXT_MESH_ID.fetch_add(1, Ordering::Relaxed),
+            version: 0,
+        }
+    }
+
+    fn bump(&mut self) {
+        self.version += 1;
+    }
+}
+
+// A copy is a separate mesh which changes on its own
+impl Clone for MeshTag {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
 }
 
+#[derive(Clone)]
 #[repr(C)]
 struct Face {
     idxs: [u32; 3],
@@ -96,53 +675,892 @@ impl fmt::Debug for Face {
     }
 }
 
-struct Layer {
-    model: glam::Mat4,
+// Axis aligned (min, max) corners
+type Bounds = (glam::Vec2, glam::Vec2);
+
+// A straight stroke segment as sent by DrawLine, in layer space
+#[derive(Clone)]
+struct Line {
+    start: glam::Vec2,
+    end: glam::Vec2,
+    thickness: f32,
+    color: [f32; 4],
+}
+
+impl Line {
+    fn mesh(&self) -> (Vec<Vertex>, Vec<Face>) {
+        let (start, end) = (self.start, self.end);
+        draw_line(start.x, start.y, end.x, end.y, self.thickness, self.color)
+    }
+
+    fn transform(&self, model: glam::Mat4) -> Line {
+        let start = model.transform_point3(self.start.extend(0.));
+        let end = model.transform_point3(self.end.extend(0.));
+        let scale = model_scale(model);
+        Line {
+            start: start.truncate(),
+            end: end.truncate(),
+            thickness: self.thickness * scale,
+            color: self.color,
+        }
+    }
+
+    // Whether next carries on drawing the same stroke as this one
+    fn continues_into(&self, next: &Line) -> bool {
+        self.end == next.start && self.thickness == next.thickness && self.color == next.color
+    }
+}
+
+// Largest coordinate or thickness a line may have. Far beyond any real
+// drawing, but small enough that summing bounds can't overflow.
+const MAX_LINE_COORD: f32 = 1e15;
+
+// Most a chunk can span in layer units before strokes go in a new one
+const CHUNK_SIZE: f32 = 0.25;
+// Keeps rebuilding a chunk's simplified meshes cheap
+const MAX_CHUNK_LINES: usize = 4096;
+
+// Number of simplified meshes kept per chunk besides the full detail one
+const LOD_LEVELS: usize = 4;
+// How far the first LOD level may stray from the original strokes, in layer units
+const LOD_BASE_TOLERANCE: f32 = 0.002;
+// Each coarser level multiplies the tolerance by this
+const LOD_STEP: f32 = 4.;
+// Most a simplified stroke may stray on screen, in physical pixels
+const LOD_MAX_ERROR_PIXELS: f32 = 1.;
+
+fn lod_tolerance(level: usize) -> f32 {
+    LOD_BASE_TOLERANCE * LOD_STEP.powi(level as i32 - 1)
+}
+
+// Coarsest LOD level which still looks right when a pixel is pixel_size
+// layer units across. Level 0 is full detail.
+fn lod_level(pixel_size: f32) -> usize {
+    (1..=LOD_LEVELS)
+        .rev()
+        .find(|&level| lod_tolerance(level) <= LOD_MAX_ERROR_PIXELS * pixel_size)
+        .unwrap_or(0)
+}
+
+// A run of strokes drawn one after the other close together. Chunks are
+// skipped as a whole when they're off screen.
+#[derive(Clone)]
+struct Chunk {
+    // Kept to rebuild the simplified meshes from
+    lines: Vec<Line>,
+    // Where the last run of connected strokes in lines starts
+    last_run: usize,
+    verts: Vec<Vertex>,
+    faces: Vec<Face>,
+    tag: MeshTag,
+    // Local space
+    bounds: Bounds,
+    // Meshes for LOD levels 1 and up, built the first time we zoom out
+    lods: OnceCell<Vec<LodMesh>>,
+}
+
+impl Chunk {
+    fn new(bounds: Bounds) -> Self {
+        Self {
+            lines: vec![],
+            last_run: 0,
+            verts: vec![],
+            faces: vec![],
+            tag: MeshTag::new(),
+            bounds,
+            lods: OnceCell::new(),
+        }
+    }
+
+    // Mesh is line.mesh() and bounds are its bounds
+    fn push_line(&mut self, line: Line, bounds: Bounds, (verts, faces): (Vec<Vertex>, Vec<Face>)) {
+        let is_new_run = !matches!(self.lines.last(), Some(last) if last.continues_into(&line));
+        if is_new_run {
+            self.last_run = self.lines.len();
+        }
+        self.lines.push(line);
+        self.bounds = union_bounds(self.bounds, bounds);
+        push_mesh(&mut self.verts, &mut self.faces, verts, faces);
+        self.tag.bump();
+
+        // Only the run this line is part of needs simplifying again
+        if let Some(lods) = self.lods.get_mut() {
+            let run = &self.lines[self.last_run..];
+            for (level, lod) in (1..).zip(lods) {
+                if is_new_run {
+                    lod.start_run();
+                }
+                lod.set_last_run(run, lod_tolerance(level));
+            }
+        }
+    }
+
+    // Vertices and faces to draw at an LOD level
+    fn mesh(&self, level: usize) -> (&MeshTag, (&[Vertex], &[Face])) {
+        if level == 0 {
+            return (&self.tag, (&self.verts, &self.faces));
+        }
+        let lods = self.lods.get_or_init(|| {
+            (1..=LOD_LEVELS)
+                .map(|level| LodMesh::new(&self.lines, self.last_run, lod_tolerance(level)))
+                .collect()
+        });
+        let lod = &lods[level - 1];
+        (&lod.tag, (&lod.verts, &lod.faces))
+    }
+}
+
+// Coarse grid over chunk bounds so culling only looks at chunks near the view
+#[derive(Clone, Default)]
+struct ChunkGrid {
+    // Indexes of the chunks touching each CHUNK_SIZE wide cell, in drawing order
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    // Chunks holding a single line too long for the grid, always checked
+    large: Vec<usize>,
+}
+
+impl ChunkGrid {
+    fn cell_range((min, max): Bounds) -> (glam::IVec2, glam::IVec2) {
+        (
+            (min / CHUNK_SIZE).floor().as_ivec2(),
+            (max / CHUNK_SIZE).floor().as_ivec2(),
+        )
+    }
+
+    // Call whenever the chunk at index grows, with its new bounds
+    fn insert(&mut self, index: usize, bounds: Bounds) {
+        let (min, max) = bounds;
+        if (max - min).max_element() > CHUNK_SIZE {
+            if self.large.last() != Some(&index) {
+                self.large.push(index);
+            }
+            return;
+        }
+        let (first, last) = Self::cell_range(bounds);
+        for y in first.y..=last.y {
+            for x in first.x..=last.x {
+                let cell = self.cells.entry((x, y)).or_default();
+                if cell.last() != Some(&index) {
+                    cell.push(index);
+                }
+            }
+        }
+    }
+
+    // Indexes of chunks which might touch bounds, in drawing order. None
+    // when bounds covers more cells than there are chunks to check.
+    fn query(&self, bounds: Bounds, chunk_count: usize) -> Option<Vec<usize>> {
+        let (first, last) = Self::cell_range(bounds);
+        let size = last.as_i64vec2() - first.as_i64vec2() + 1;
+        if size.x * size.y > chunk_count as i64 {
+            return None;
+        }
+        let mut indexes = self.large.clone();
+        for y in first.y..=last.y {
+            for x in first.x..=last.x {
+                if let Some(cell) = self.cells.get(&(x, y)) {
+                    indexes.extend(cell);
+                }
+            }
+        }
+        indexes.sort_unstable();
+        indexes.dedup();
+        Some(indexes)
+    }
+}
+
+// Simplified mesh of a chunk at one LOD level
+#[derive(Clone)]
+struct LodMesh {
     verts: Vec<Vertex>,
     faces: Vec<Face>,
+    tag: MeshTag,
+    // Where the last run starts, so it can be redone on its own as it grows
+    last_run_verts: usize,
+    last_run_faces: usize,
+}
+
+impl LodMesh {
+    fn new(lines: &[Line], last_run: usize, tolerance: f32) -> Self {
+        let (verts, faces) = simplify_lines(&lines[..last_run], tolerance);
+        let mut lod = Self {
+            last_run_verts: verts.len(),
+            last_run_faces: faces.len(),
+            verts,
+            faces,
+            tag: MeshTag::new(),
+        };
+        lod.set_last_run(&lines[last_run..], tolerance);
+        lod
+    }
+
+    // Whatever gets added next is a new run
+    fn start_run(&mut self) {
+        self.last_run_verts = self.verts.len();
+        self.last_run_faces = self.faces.len();
+    }
+
+    // Replaces the mesh of the last run
+    fn set_last_run(&mut self, run: &[Line], tolerance: f32) {
+        self.verts.truncate(self.last_run_verts);
+        self.faces.truncate(self.last_run_faces);
+        let (verts, faces) = simplify_lines(run, tolerance);
+        push_mesh(&mut self.verts, &mut self.faces, verts, faces);
+        self.tag.bump();
+    }
+}
+
+// Mesh for lines with each run of connected strokes simplified
+fn simplify_lines(lines: &[Line], tolerance: f32) -> (Vec<Vertex>, Vec<Face>) {
+    let mut verts = vec![];
+    let mut faces = vec![];
+    let mut points = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        if points.is_empty() {
+            points.push(line.start);
+        }
+        points.push(line.end);
+        if let Some(next) = lines.get(i + 1) {
+            if line.continues_into(next) {
+                continue;
+            }
+        }
+
+        for pair in simplify_polyline(&points, tolerance).windows(2) {
+            let (start, end) = (pair[0], pair[1]);
+            #[rustfmt::skip]
+            let (line_verts, line_faces) = draw_line(start.x, start.y, end.x, end.y, line.thickness, line.color);
+            push_mesh(&mut verts, &mut faces, line_verts, line_faces);
+        }
+        points.clear();
+    }
+    (verts, faces)
+}
+
+// Douglas-Peucker. Drops points until the polyline would stray further than
+// tolerance from the original.
+fn simplify_polyline(points: &[glam::Vec2], tolerance: f32) -> Vec<glam::Vec2> {
+    if points.len() <= 2 {
+        return points.to_vec();
+    }
+    let mut is_kept = vec![false; points.len()];
+    is_kept[0] = true;
+    is_kept[points.len() - 1] = true;
+
+    let mut spans = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = spans.pop() {
+        let (start, end) = (points[first], points[last]);
+        let furthest = (first + 1..last)
+            .map(|i| (i, distance_to_segment(points[i], start, end)))
+            .max_by(|(_, dist1), (_, dist2)| dist1.total_cmp(dist2));
+        if let Some((i, dist)) = furthest {
+            if dist > tolerance {
+                is_kept[i] = true;
+                spans.push((first, i));
+                spans.push((i, last));
+            }
+        }
+    }
+    points
+        .iter()
+        .zip(is_kept)
+        .filter(|(_, is_kept)| *is_kept)
+        .map(|(point, _)| *point)
+        .collect()
+}
+
+fn distance_to_segment(point: glam::Vec2, start: glam::Vec2, end: glam::Vec2) -> f32 {
+    let dir = end - start;
+    let len_sq = dir.length_squared();
+    if len_sq == 0. {
+        return point.distance(start);
+    }
+    let t = ((point - start).dot(dir) / len_sq).clamp(0., 1.);
+    point.distance(start + t * dir)
+}
+
+// Layers are nested using paths like "peer/alice/ink". Every prefix of a path
+// is itself a layer (a group), and its model, visibility and opacity cascade
+// down to everything below it.
+#[derive(Clone)]
+struct Layer {
+    model: glam::Mat4,
+    // Geometry in local space, in the order it was drawn
+    chunks: Vec<Chunk>,
+    chunk_grid: ChunkGrid,
+    // Overlays are never culled, so they keep everything in one chunk
+    is_chunked: bool,
     is_hidden: bool,
+    opacity: f32,
+    // Locked layers reject drawing and any other edits. Only the owner can unlock.
+    is_locked: bool,
+    owner: Option<String>,
+    strokes: u64,
+    // Arbitrary key-values set by scripts
+    metadata: BTreeMap<String, String>,
+    // Drawn over everything in screen pixels, ignoring the camera.
+    // Used for cursors, toolbars and other overlays.
+    is_screen_space: bool,
 }
 
 impl Layer {
     fn new() -> Self {
         Self {
             model: glam::Mat4::IDENTITY,
-            verts: vec![],
-            faces: vec![],
+            chunks: vec![],
+            chunk_grid: ChunkGrid::default(),
+            is_chunked: true,
             is_hidden: false,
+            opacity: 1.,
+            is_locked: false,
+            owner: None,
+            strokes: 0,
+            metadata: BTreeMap::new(),
+            is_screen_space: false,
+        }
+    }
+
+    // Bounding box of the vertices before the model is applied
+    fn local_bounds(&self) -> Option<Bounds> {
+        self.chunks
+            .iter()
+            .map(|chunk| chunk.bounds)
+            .reduce(union_bounds)
+    }
+
+    // Mesh is line.mesh(), passed in so callers can check it first
+    fn push_line(&mut self, line: Line, (verts, faces): (Vec<Vertex>, Vec<Face>)) {
+        let Some(bounds) = mesh_bounds(&verts) else {
+            return;
+        };
+        // Only ever add to the newest chunk so the drawing order is kept
+        let is_full = match self.chunks.last() {
+            Some(chunk) => {
+                let (min, max) = union_bounds(chunk.bounds, bounds);
+                self.is_chunked
+                    && ((max - min).max_element() > CHUNK_SIZE
+                        || chunk.lines.len() >= MAX_CHUNK_LINES)
+            }
+            None => true,
+        };
+        if is_full {
+            self.chunks.push(Chunk::new(bounds));
+        }
+        let index = self.chunks.len() - 1;
+        let chunk = &mut self.chunks[index];
+        chunk.push_line(line, bounds, (verts, faces));
+        self.chunk_grid.insert(index, chunk.bounds);
+    }
+
+    fn clear_lines(&mut self) {
+        self.chunks.clear();
+        self.chunk_grid = ChunkGrid::default();
+    }
+
+    // Rebuilds the chunks when switching between world space and overlay
+    fn set_chunked(&mut self, is_chunked: bool) {
+        if self.is_chunked == is_chunked {
+            return;
+        }
+        self.is_chunked = is_chunked;
+        let chunks = std::mem::take(&mut self.chunks);
+        self.chunk_grid = ChunkGrid::default();
+        for line in chunks.into_iter().flat_map(|chunk| chunk.lines) {
+            let mesh = line.mesh();
+            self.push_line(line, mesh);
+        }
+    }
+
+    fn vert_count(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.verts.len()).sum()
+    }
+
+    fn face_count(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.faces.len()).sum()
+    }
+
+    // Chunks which might be seen in view. Both model and view are world space.
+    fn visible_chunks(&self, model: glam::Mat4, view: Bounds) -> Vec<&Chunk> {
+        let view = transform_bounds(model.inverse(), view);
+        let is_visible = |chunk: &&Chunk| bounds_intersect(chunk.bounds, view);
+        match self.chunk_grid.query(view, self.chunks.len()) {
+            Some(indexes) => indexes
+                .into_iter()
+                .map(|index| &self.chunks[index])
+                .filter(is_visible)
+                .collect(),
+            // Zoomed out far enough that most chunks are on screen anyway
+            None => self.chunks.iter().filter(is_visible).collect(),
         }
     }
 }
 
-struct Stage {
+fn mesh_bounds(verts: &[Vertex]) -> Option<Bounds> {
+    let mut verts = verts.iter().map(|vert| glam::Vec2::from(vert.pos));
+    let first = verts.next()?;
+    Some(verts.fold((first, first), |(min, max), pos| {
+        (min.min(pos), max.max(pos))
+    }))
+}
+
+// Append a mesh, offsetting its indices past the existing vertices
+fn push_mesh(
+    verts: &mut Vec<Vertex>,
+    faces: &mut Vec<Face>,
+    mut new_verts: Vec<Vertex>,
+    mut new_faces: Vec<Face>,
+) {
+    let offset = verts.len() as u32;
+    verts.append(&mut new_verts);
+    for face in &mut new_faces {
+        for idx in &mut face.idxs {
+            *idx += offset;
+        }
+    }
+    faces.append(&mut new_faces);
+}
+
+// Axis aligned bounds of a box after it's been transformed
+fn transform_bounds(model: glam::Mat4, (min, max): Bounds) -> Bounds {
+    let corners = [
+        glam::vec2(min.x, min.y),
+        glam::vec2(max.x, min.y),
+        glam::vec2(min.x, max.y),
+        glam::vec2(max.x, max.y),
+    ]
+    .map(|corner| model.transform_point3(corner.extend(0.)).truncate());
+    corners[1..]
+        .iter()
+        .fold((corners[0], corners[0]), |(min, max), &corner| {
+            (min.min(corner), max.max(corner))
+        })
+}
+
+// Average of how much a model stretches things in x and y
+fn model_scale(model: glam::Mat4) -> f32 {
+    let linear = glam::Mat2::from_mat3(glam::Mat3::from_mat4(model));
+    linear.determinant().abs().sqrt()
+}
+
+fn bounds_intersect((min1, max1): Bounds, (min2, max2): Bounds) -> bool {
+    min1.cmple(max2).all() && min2.cmple(max1).all()
+}
+
+fn union_bounds((min1, max1): Bounds, (min2, max2): Bounds) -> Bounds {
+    (min1.min(min2), max1.max(max2))
+}
+
+// A layer's state once all its parent groups are applied
+struct ResolvedLayer {
+    model: glam::Mat4,
+    opacity: f32,
+    is_screen_space: bool,
+}
+
+fn parent_layer_name(layer_name: &str) -> Option<&str> {
+    layer_name.rsplit_once('/').map(|(parent, _)| parent)
+}
+
+// Is the layer either root itself or somewhere below it?
+fn is_in_subtree(layer_name: &str, root: &str) -> bool {
+    match layer_name.strip_prefix(root) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+// How many logical pixels one world unit covers at zoom 1
+const PIXELS_PER_UNIT: f32 = 1250.;
+
+// How world units map to the screen
+#[derive(Clone, Copy)]
+enum ViewScaling {
+    // Old behaviour which ignores the DPI, so boards look smaller on HiDPI screens
+    Legacy,
+    // World units are a fixed number of logical pixels
+    Dpi,
+}
+
+// Past these limits f32 precision starts to collapse
+const MIN_ZOOM: f32 = 0.001;
+const MAX_ZOOM: f32 = 10000.;
+// Fraction of the screen left around the content by FitAll
+const DEFAULT_FIT_PADDING: f32 = 0.05;
+
+// The world position shown at the centre of the screen, how far we're zoomed
+// in and how much the board is rotated counter-clockwise (in radians).
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Camera {
+    center: glam::Vec2,
+    zoom: f32,
+    rotation: f32,
+}
+
+impl Camera {
+    fn new() -> Self {
+        Self {
+            center: glam::Vec2::ZERO,
+            zoom: 1.,
+            rotation: 0.,
+        }
+    }
+
+    // A view sent by a client, or None if it would break the projection
+    fn from_view(x: f32, y: f32, zoom: f32, rotation: f32) -> Option<Self> {
+        if ![x, y, zoom, rotation].iter().all(|val| val.is_finite()) || zoom <= 0. {
+            return None;
+        }
+        Some(Self {
+            center: glam::vec2(x, y),
+            zoom: zoom.clamp(MIN_ZOOM, MAX_ZOOM),
+            rotation: wrap_angle(rotation),
+        })
+    }
+
+    // World to view space. The screen scaling is applied afterwards.
+    fn view_matrix(&self) -> glam::Mat4 {
+        glam::Mat4::from_scale(glam::Vec3::new(self.zoom, self.zoom, 1.))
+            * glam::Mat4::from_rotation_z(self.rotation)
+            * glam::Mat4::from_translation(-self.center.extend(0.))
+    }
+
+    // Zoom while keeping the world point anchor fixed on the screen
+    fn zoom_at(&mut self, scale: f32, anchor: glam::Vec2) {
+        let zoom = (self.zoom * scale).clamp(MIN_ZOOM, MAX_ZOOM);
+        // Might be less than asked for once clamped
+        let scale = zoom / self.zoom;
+        self.center = anchor + (self.center - anchor) / scale;
+        self.zoom = zoom;
+    }
+
+    // Rotate counter-clockwise while keeping the world point anchor fixed on the screen
+    fn rotate_at(&mut self, angle: f32, anchor: glam::Vec2) {
+        self.center = anchor - glam::Vec2::from_angle(-angle).rotate(anchor - self.center);
+        self.rotation = wrap_angle(self.rotation + angle);
+    }
+
+    // Zoom is interpolated geometrically so each step feels the same size,
+    // and rotation takes the shortest way around.
+    fn lerp(&self, other: &Camera, t: f32) -> Camera {
+        Camera {
+            center: self.center.lerp(other.center, t),
+            zoom: self.zoom * (other.zoom / self.zoom).powf(t),
+            rotation: wrap_angle(self.rotation + wrap_angle(other.rotation - self.rotation) * t),
+        }
+    }
+}
+
+// Moves the camera smoothly between two views. Advanced from update().
+// Longest a camera move may take, so a bad duration can't freeze the view
+const MAX_ANIMATION_DURATION: f32 = 10.;
+
+struct CameraAnimation {
+    from: Camera,
+    to: Camera,
+    start: time::Instant,
+    // Seconds
+    duration: f32,
+}
+
+impl CameraAnimation {
+    fn camera_at(&self, now: time::Instant) -> Camera {
+        let t = now.duration_since(self.start).as_secs_f32() / self.duration;
+        self.from.lerp(&self.to, ease_in_out(t.min(1.)))
+    }
+
+    fn is_finished(&self, now: time::Instant) -> bool {
+        now.duration_since(self.start).as_secs_f32() >= self.duration
+    }
+}
+
+// Cubic ease in/out for t between (0, 1)
+fn ease_in_out(t: f32) -> f32 {
+    if t < 0.5 {
+        4. * t * t * t
+    } else {
+        1. - (-2. * t + 2.).powi(3) / 2.
+    }
+}
+
+// Keep angles within [-π, π)
+fn wrap_angle(angle: f32) -> f32 {
+    use std::f32::consts::{PI, TAU};
+    (angle + PI).rem_euclid(TAU) - PI
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum BackgroundMode {
+    Plain = 0,
+    Grid = 1,
+    Dots = 2,
+    Ruled = 3,
+    Isometric = 4,
+}
+
+impl BackgroundMode {
+    fn from_u8(mode: u8) -> Option<Self> {
+        match mode {
+            0 => Some(Self::Plain),
+            1 => Some(Self::Grid),
+            2 => Some(Self::Dots),
+            3 => Some(Self::Ruled),
+            4 => Some(Self::Isometric),
+            _ => None,
+        }
+    }
+}
+
+// Patterns closer than this many logical pixels get coarsened
+const MIN_GRID_PIXELS: f32 = 16.;
+// Each coarser (or finer) level multiplies the spacing by this
+const GRID_STEP: f32 = 5.;
+
+// The paper drawn underneath all the layers
+struct Background {
+    mode: BackgroundMode,
+    color: [f32; 4],
+    line_color: [f32; 4],
+    // World units between lines, before adapting to the zoom
+    spacing: f32,
+}
+
+impl Background {
+    fn new() -> Self {
+        Self {
+            mode: BackgroundMode::Plain,
+            color: [0., 0., 0., 1.],
+            line_color: [1., 1., 1., 0.15],
+            spacing: 0.02,
+        }
+    }
+}
+
+// A slide in presentation mode
+struct Frame {
+    bounds: Bounds,
+    // Slides are shown in this order, then by name
+    order: u32,
+}
+
+// Overview of the whole board in the bottom right corner, in logical pixels
+const MINIMAP_SIZE: f32 = 200.;
+const MINIMAP_MARGIN: f32 = 16.;
+// Seconds to glide over to a point clicked on the minimap
+const MINIMAP_JUMP_DURATION: f32 = 0.25;
+
+// Physical pixels with the origin at the top left, same as mouse events
+#[derive(Clone, Copy, PartialEq)]
+struct ScreenRect {
+    x: f32,
+    y: f32,
+    w: f32,
+    h: f32,
+}
+
+impl ScreenRect {
+    fn full_screen() -> Self {
+        let (screen_width, screen_height) = window::screen_size();
+        Self {
+            x: 0.,
+            y: 0.,
+            w: screen_width,
+            h: screen_height,
+        }
+    }
+
+    fn contains(&self, x: f32, y: f32) -> bool {
+        self.x <= x && x < self.x + self.w && self.y <= y && y < self.y + self.h
+    }
+}
+
+// An extra view drawn over part of the window, on top of the main view
+struct Viewport {
+    // Screen coords in (0, 1) so it follows the window size
+    rect: Bounds,
+    camera: Camera,
+    // Only these layers and their subtrees are drawn. Empty draws everything.
+    layer_filter: Vec<String>,
+}
+
+impl Viewport {
+    fn screen_rect(&self) -> ScreenRect {
+        let (screen_width, screen_height) = window::screen_size();
+        let (min, max) = self.rect;
+        ScreenRect {
+            x: min.x * screen_width,
+            y: min.y * screen_height,
+            w: (max.x - min.x) * screen_width,
+            h: (max.y - min.y) * screen_height,
+        }
+    }
+
+    fn shows_layer(&self, layer_name: &str) -> bool {
+        self.layer_filter.is_empty()
+            || self
+                .layer_filter
+                .iter()
+                .any(|root| is_in_subtree(layer_name, root))
+    }
+}
+
+// Closed outline through the corners
+fn draw_outline(
+    corners: &[glam::Vec2],
+    thickness: f32,
+    color: [f32; 4],
+    verts: &mut Vec<Vertex>,
+    faces: &mut Vec<Face>,
+) {
+    for (i, start) in corners.iter().enumerate() {
+        let end = corners[(i + 1) % corners.len()];
+        let (line_verts, line_faces) = draw_line(start.x, start.y, end.x, end.y, thickness, color);
+        push_mesh(verts, faces, line_verts, line_faces);
+    }
+}
+
+// Parallel lines at angle, spacing apart, covering the bounds
+#[rustfmt::skip]
+fn parallel_lines(
+    bounds: Bounds, angle: f32, spacing: f32,
+    thickness: f32, color: [f32; 4],
+    verts: &mut Vec<Vertex>, faces: &mut Vec<Face>,
+) {
+    // Work in a space where the lines are horizontal
+    let (min, max) = transform_bounds(glam::Mat4::from_rotation_z(-angle), bounds);
+    let rotation = glam::Vec2::from_angle(angle);
+
+    let first = (min.y / spacing).ceil() as i64;
+    let last = (max.y / spacing).floor() as i64;
+    for i in first..=last {
+        let y = i as f32 * spacing;
+        let start = rotation.rotate(glam::vec2(min.x, y));
+        let end = rotation.rotate(glam::vec2(max.x, y));
+        let (line_verts, line_faces) = draw_line(start.x, start.y, end.x, end.y, thickness, color);
+        push_mesh(verts, faces, line_verts, line_faces);
+    }
+}
+
+// Meshes which haven't been drawn for this many frames get their GPU
+// buffers freed. Also covers meshes which no longer exist.
+const MESH_KEEP_FRAMES: u64 = 300;
+// Starting size in elements of the buffers for meshes built every frame
+const FRAME_BUFFER_LEN: usize = 4096;
+
+// Same layout and order as the uniforms in the shader meta
+#[repr(C)]
+struct Uniforms {
+    projection: glam::Mat4,
+    model: glam::Mat4,
+    // Half the screen size, taking clip space to physical pixels
+    pixel_scale: [f32; 2],
+    min_stroke_width: f32,
+    // 1 to feather stroke edges, 0 not to
+    antialiasing: f32,
+    opacity: f32,
+}
+
+// Buffers of a mesh which stays on the GPU between frames
+struct GpuMesh {
+    version: u64,
+    vertex_buffer: BufferId,
+    index_buffer: BufferId,
+    last_frame: u64,
+}
+
+// Where a draw call's faces come from
+#[derive(Clone, Copy, PartialEq)]
+enum MeshSource {
+    // Built this frame, like the background and minimap
+    Frame,
+    // A GpuMesh by MeshTag id
+    Kept(u64),
+}
+
+// A range of faces drawn with the same buffers and uniforms
+struct DrawCall {
+    source: MeshSource,
+    proj: glam::Mat4,
+    clip: ScreenRect,
+    model: glam::Mat4,
+    opacity: f32,
+    first_face: usize,
+    face_count: usize,
+}
+
+impl DrawCall {
+    fn has_same_uniforms(&self, other: &DrawCall) -> bool {
+        self.proj == other.proj
+            && self.clip == other.clip
+            && self.model == other.model
+            && self.opacity == other.opacity
+    }
+
+    // Can next be drawn as part of this call
+    fn can_merge(&self, next: &DrawCall) -> bool {
+        self.source == MeshSource::Frame
+            && next.source == MeshSource::Frame
+            && self.has_same_uniforms(next)
+            && self.first_face + self.face_count == next.first_face
+    }
+}
+
+// Faces as the u32 indices index buffers expect
+fn index_source(faces: &[Face]) -> BufferSource<'_> {
+    unsafe {
+        BufferSource::pointer(
+            faces.as_ptr() as _,
+            std::mem::size_of_val(faces),
+            std::mem::size_of::<u32>(),
+        )
+    }
+}
+
+// GPU state needed to draw meshes. Layer meshes keep their buffers between
+// frames and are only uploaded again when they change. Each frame just
+// collects draw calls, then runs them in a single render pass.
+struct Renderer {
     ctx: Box<dyn RenderingBackend>,
     pipeline: Pipeline,
     white_texture: TextureId,
-    layers: HashMap<String, Layer>,
+    clear_color: [f32; 4],
+    // Feather stroke edges in the fragment shader
+    is_antialiased: bool,
+    // Strokes thinner than this many physical pixels get drawn this wide
+    min_stroke_width: f32,
+    // Set by set_view() for the meshes which follow
     proj: glam::Mat4,
-    // req-reply commands
-    req_socket: zmq::Socket,
-    // events from this canvas
-    pub_socket: zmq::Socket,
-    // low latency no reply commands
-    sub_socket: zmq::Socket,
+    clip: ScreenRect,
+    // Meshes built this frame, streamed into buffers which only ever grow
+    frame_verts: Vec<Vertex>,
+    frame_faces: Vec<Face>,
+    frame_vertex_buffer: BufferId,
+    frame_index_buffer: BufferId,
+    // By MeshTag id
+    kept_meshes: HashMap<u64, GpuMesh>,
+    frame: u64,
+    draws: Vec<DrawCall>,
 }
 
-impl Stage {
-    pub fn new() -> Stage {
+impl Renderer {
+    fn new() -> Self {
         let mut ctx: Box<dyn RenderingBackend> = window::new_rendering_backend();
 
         let white_texture = ctx.new_texture_from_rgba8(1, 1, &[255, 255, 255, 255]);
 
         let mut shader_meta: ShaderMeta = shader::meta();
-        shader_meta
-            .uniforms
-            .uniforms
-            .push(UniformDesc::new("Model", UniformType::Mat4));
-        shader_meta
-            .uniforms
-            .uniforms
-            .push(UniformDesc::new("Projection", UniformType::Mat4));
+        for (name, uniform_type) in [
+            ("Projection", UniformType::Mat4),
+            ("Model", UniformType::Mat4),
+            ("PixelScale", UniformType::Float2),
+            ("MinStrokeWidth", UniformType::Float1),
+            ("Antialiasing", UniformType::Float1),
+            ("Opacity", UniformType::Float1),
+        ] {
+            shader_meta
+                .uniforms
+                .uniforms
+                .push(UniformDesc::new(name, uniform_type));
+        }
 
         let shader = ctx
             .new_shader(
@@ -159,25 +1577,419 @@ impl Stage {
             )
             .unwrap();
 
-        let params = PipelineParams {
-            color_blend: Some(BlendState::new(
-                Equation::Add,
-                BlendFactor::Value(BlendValue::SourceAlpha),
-                BlendFactor::OneMinusValue(BlendValue::SourceAlpha),
-            )),
-            ..Default::default()
-        };
+        let params = PipelineParams {
+            color_blend: Some(BlendState::new(
+                Equation::Add,
+                BlendFactor::Value(BlendValue::SourceAlpha),
+                BlendFactor::OneMinusValue(BlendValue::SourceAlpha),
+            )),
+            ..Default::default()
+        };
+
+        let pipeline = ctx.new_pipeline(
+            &[BufferLayout::default()],
+            &[
+                VertexAttribute::new("in_pos", VertexFormat::Float2),
+                VertexAttribute::new("in_color", VertexFormat::Float4),
+                VertexAttribute::new("in_uv", VertexFormat::Float2),
+                VertexAttribute::new("in_normal", VertexFormat::Float2),
+            ],
+            shader,
+            params,
+        );
+
+        let frame_vertex_buffer = ctx.new_buffer(
+            BufferType::VertexBuffer,
+            BufferUsage::Stream,
+            BufferSource::empty::<Vertex>(FRAME_BUFFER_LEN),
+        );
+        let frame_index_buffer = ctx.new_buffer(
+            BufferType::IndexBuffer,
+            BufferUsage::Stream,
+            BufferSource::empty::<u32>(3 * FRAME_BUFFER_LEN),
+        );
+
+        Self {
+            ctx,
+            pipeline,
+            white_texture,
+            clear_color: [0., 0., 0., 1.],
+            is_antialiased: true,
+            min_stroke_width: 1.,
+            proj: glam::Mat4::IDENTITY,
+            clip: ScreenRect::full_screen(),
+            frame_verts: vec![],
+            frame_faces: vec![],
+            frame_vertex_buffer,
+            frame_index_buffer,
+            kept_meshes: HashMap::new(),
+            frame: 0,
+            draws: vec![],
+        }
+    }
+
+    fn begin_frame(&mut self, clear_color: [f32; 4]) {
+        self.clear_color = clear_color;
+        self.frame_verts.clear();
+        self.frame_faces.clear();
+        self.draws.clear();
+    }
+
+    // Projection and clip rect for the meshes drawn after this
+    fn set_view(&mut self, proj: glam::Mat4, clip: ScreenRect) {
+        self.proj = proj;
+        self.clip = clip;
+    }
+
+    fn push_draw(&mut self, draw: DrawCall) {
+        match self.draws.last_mut() {
+            Some(last) if last.can_merge(&draw) => last.face_count += draw.face_count,
+            _ => self.draws.push(draw),
+        }
+    }
+
+    // Draws a mesh which was built for this frame only
+    fn draw_mesh(&mut self, verts: &[Vertex], faces: &[Face], model: &glam::Mat4, opacity: f32) {
+        if faces.is_empty() {
+            return;
+        }
+        let offset = self.frame_verts.len() as u32;
+        let first_face = self.frame_faces.len();
+        self.frame_verts.extend_from_slice(verts);
+        self.frame_faces.extend(faces.iter().map(|face| Face {
+            idxs: face.idxs.map(|idx| idx + offset),
+        }));
+        self.push_draw(DrawCall {
+            source: MeshSource::Frame,
+            proj: self.proj,
+            clip: self.clip,
+            model: *model,
+            opacity,
+            first_face,
+            face_count: faces.len(),
+        });
+    }
+
+    // Draws a mesh which stays on the GPU, uploading it first if it's new
+    // or has changed since last time
+    fn draw_kept_mesh(
+        &mut self,
+        tag: &MeshTag,
+        (verts, faces): (&[Vertex], &[Face]),
+        model: &glam::Mat4,
+        opacity: f32,
+    ) {
+        if faces.is_empty() {
+            return;
+        }
+        let is_current = matches!(
+            self.kept_meshes.get(&tag.id),
+            Some(mesh) if mesh.version == tag.version
+        );
+        if !is_current {
+            if let Some(old) = self.kept_meshes.remove(&tag.id) {
+                self.ctx.delete_buffer(old.vertex_buffer);
+                self.ctx.delete_buffer(old.index_buffer);
+            }
+            let vertex_buffer = self.ctx.new_buffer(
+                BufferType::VertexBuffer,
+                BufferUsage::Immutable,
+                BufferSource::slice(verts),
+            );
+            let index_buffer = self.ctx.new_buffer(
+                BufferType::IndexBuffer,
+                BufferUsage::Immutable,
+                index_source(faces),
+            );
+            self.kept_meshes.insert(
+                tag.id,
+                GpuMesh {
+                    version: tag.version,
+                    vertex_buffer,
+                    index_buffer,
+                    last_frame: self.frame,
+                },
+            );
+        }
+        self.kept_meshes.get_mut(&tag.id).unwrap().last_frame = self.frame;
+        self.push_draw(DrawCall {
+            source: MeshSource::Kept(tag.id),
+            proj: self.proj,
+            clip: self.clip,
+            model: *model,
+            opacity,
+            first_face: 0,
+            face_count: faces.len(),
+        });
+    }
+
+    // Copies this frame's meshes to the GPU, growing the buffers if needed
+    fn upload_frame_meshes(&mut self) {
+        if self.frame_faces.is_empty() {
+            return;
+        }
+        let vertex_size = std::mem::size_of_val(self.frame_verts.as_slice());
+        if self.ctx.buffer_size(self.frame_vertex_buffer) < vertex_size {
+            self.ctx.delete_buffer(self.frame_vertex_buffer);
+            self.frame_vertex_buffer = self.ctx.new_buffer(
+                BufferType::VertexBuffer,
+                BufferUsage::Stream,
+                BufferSource::empty::<Vertex>(self.frame_verts.len().next_power_of_two()),
+            );
+        }
+        let index_size = std::mem::size_of_val(self.frame_faces.as_slice());
+        if self.ctx.buffer_size(self.frame_index_buffer) < index_size {
+            self.ctx.delete_buffer(self.frame_index_buffer);
+            self.frame_index_buffer = self.ctx.new_buffer(
+                BufferType::IndexBuffer,
+                BufferUsage::Stream,
+                BufferSource::empty::<u32>(3 * self.frame_faces.len().next_power_of_two()),
+            );
+        }
+        self.ctx.buffer_update(
+            self.frame_vertex_buffer,
+            BufferSource::slice(&self.frame_verts),
+        );
+        self.ctx
+            .buffer_update(self.frame_index_buffer, index_source(&self.frame_faces));
+    }
+
+    fn end_frame(&mut self) {
+        self.upload_frame_meshes();
+
+        let [r, g, b, a] = self.clear_color;
+        self.ctx
+            .begin_default_pass(PassAction::clear_color(r, g, b, a));
+        let (screen_width, screen_height) = window::screen_size();
+        self.ctx.apply_pipeline(&self.pipeline);
+        self.ctx
+            .apply_viewport(0, 0, screen_width as i32, screen_height as i32);
+
+        // Chunks of the same layer share uniforms, so only set them on changes
+        let mut bound = None;
+        let mut prev_draw: Option<&DrawCall> = None;
+        for draw in &self.draws {
+            if bound != Some(draw.source) {
+                let (vertex_buffer, index_buffer) = match draw.source {
+                    MeshSource::Frame => (self.frame_vertex_buffer, self.frame_index_buffer),
+                    MeshSource::Kept(id) => {
+                        let mesh = &self.kept_meshes[&id];
+                        (mesh.vertex_buffer, mesh.index_buffer)
+                    }
+                };
+                self.ctx.apply_bindings(&Bindings {
+                    vertex_buffers: vec![vertex_buffer],
+                    index_buffer,
+                    images: vec![self.white_texture],
+                });
+                bound = Some(draw.source);
+            }
+
+            if !prev_draw.is_some_and(|prev| prev.has_same_uniforms(draw)) {
+                let clip = draw.clip;
+                // Scissor rects start at the bottom left
+                #[rustfmt::skip]
+                self.ctx.apply_scissor_rect(
+                    clip.x as i32, (screen_height - clip.y - clip.h) as i32,
+                    clip.w as i32, clip.h as i32,
+                );
+
+                let uniforms = Uniforms {
+                    projection: draw.proj,
+                    model: draw.model,
+                    pixel_scale: [screen_width / 2., screen_height / 2.],
+                    min_stroke_width: self.min_stroke_width,
+                    antialiasing: if self.is_antialiased { 1. } else { 0. },
+                    opacity: draw.opacity,
+                };
+                self.ctx.apply_uniforms(UniformsSource::table(&uniforms));
+            }
+            prev_draw = Some(draw);
+
+            #[rustfmt::skip]
+            self.ctx.draw(
+                3 * draw.first_face as i32, 3 * draw.face_count as i32,
+                1,
+            );
+        }
+        self.ctx.end_render_pass();
+        self.ctx.commit_frame();
+
+        // Free meshes which are gone or haven't been on screen for a while
+        let frame = self.frame;
+        let ctx = &mut self.ctx;
+        self.kept_meshes.retain(|_, mesh| {
+            let is_kept = frame - mesh.last_frame < MESH_KEEP_FRAMES;
+            if !is_kept {
+                ctx.delete_buffer(mesh.vertex_buffer);
+                ctx.delete_buffer(mesh.index_buffer);
+            }
+            is_kept
+        });
+        self.frame += 1;
+    }
+}
+
+// Commands waiting for the main thread. Once it's full the network thread
+// stops reading and zmq queues up (or drops) messages until we catch up.
+const COMMAND_QUEUE_LEN: usize = 1024;
+// Most time update() spends applying commands before letting the frame draw
+const COMMAND_BUDGET: time::Duration = time::Duration::from_millis(8);
+
+// Read off the sockets by the network thread, applied by Stage in update()
+enum NetMessage {
+    // Needs a reply, sent back through Stage::reply_socket
+    Req(ReqCommand),
+    Sub(SubCommand),
+}
+
+// Backpressure counters shared with the network thread
+#[derive(Default)]
+struct NetStats {
+    received: AtomicU64,
+    // Times the network thread had to wait for room in the queue
+    stalls: AtomicU64,
+    queued: AtomicU64,
+    max_queued: AtomicU64,
+    // Frames which ran out of budget with commands still queued
+    deferred_frames: AtomicU64,
+}
+
+// Messages are [cmd, payload] with a single byte cmd
+fn recv_command(socket: &zmq::Socket) -> std::io::Result<(Command, Vec<u8>)> {
+    let req = socket.recv_multipart(0).unwrap();
+    let Ok([cmd, payload]) = <[Vec<u8>; 2]>::try_from(req) else {
+        return Err(invalid_data("expected a command and a payload"));
+    };
+    let [cmd] = cmd[..] else {
+        return Err(invalid_data("command must be a single byte"));
+    };
+    let cmd = Command::from_u8(cmd).ok_or_else(|| invalid_data("unknown command"))?;
+    Ok((cmd, payload))
+}
+
+// Returns false once the main thread has gone away
+fn queue_command(
+    command_tx: &mpsc::SyncSender<NetMessage>,
+    stats: &NetStats,
+    msg: NetMessage,
+) -> bool {
+    stats.received.fetch_add(1, Ordering::Relaxed);
+    let queued = stats.queued.fetch_add(1, Ordering::Relaxed) + 1;
+    stats.max_queued.fetch_max(queued, Ordering::Relaxed);
+    let is_sent = match command_tx.try_send(msg) {
+        Ok(()) => true,
+        Err(mpsc::TrySendError::Full(msg)) => {
+            stats.stalls.fetch_add(1, Ordering::Relaxed);
+            command_tx.send(msg).is_ok()
+        }
+        Err(mpsc::TrySendError::Disconnected(_)) => false,
+    };
+    // The event loop sleeps until something needs doing
+    window::schedule_update();
+    is_sent
+}
+
+// Runs on its own thread so network bursts can't hold up rendering
+fn run_network(
+    req_socket: zmq::Socket,
+    sub_socket: zmq::Socket,
+    reply_socket: zmq::Socket,
+    command_tx: mpsc::SyncSender<NetMessage>,
+    stats: Arc<NetStats>,
+) {
+    // A REP socket won't take the next request until we've replied
+    let mut is_awaiting_reply = false;
+    loop {
+        let req_events = if is_awaiting_reply {
+            zmq::PollEvents::empty()
+        } else {
+            zmq::POLLIN
+        };
+        let mut items = [
+            req_socket.as_poll_item(req_events),
+            sub_socket.as_poll_item(zmq::POLLIN),
+            reply_socket.as_poll_item(zmq::POLLIN),
+        ];
+        zmq::poll(&mut items, -1).unwrap();
+        let is_readable = items.map(|item| item.is_readable());
+
+        if is_readable[2] {
+            let reply = reply_socket.recv_bytes(0).unwrap();
+            req_socket.send(reply, 0).unwrap();
+            is_awaiting_reply = false;
+        }
+        if is_readable[0] {
+            let req = recv_command(&req_socket)
+                .and_then(|(cmd, payload)| ReqCommand::decode(cmd, &payload));
+            match req {
+                Ok(req) => {
+                    if !queue_command(&command_tx, &stats, NetMessage::Req(req)) {
+                        return;
+                    }
+                    is_awaiting_reply = true;
+                }
+                // The main thread never sees it, so reply here. Every real
+                // reply has at least one byte, so empty means rejected.
+                Err(err) => {
+                    warn!("Rejected request: {}", err);
+                    req_socket.send(vec![], 0).unwrap();
+                }
+            }
+        }
+        if is_readable[1] {
+            let sub = recv_command(&sub_socket)
+                .and_then(|(cmd, payload)| SubCommand::decode(cmd, &payload));
+            match sub {
+                Ok(sub) => {
+                    if !queue_command(&command_tx, &stats, NetMessage::Sub(sub)) {
+                        return;
+                    }
+                }
+                // Nobody to tell on this socket
+                Err(err) => warn!("Dropped update: {}", err),
+            }
+        }
+    }
+}
+
+struct Stage {
+    renderer: Renderer,
+    // Sorted so parent groups always come before their children
+    layers: BTreeMap<String, Layer>,
+    camera: Camera,
+    camera_anim: Option<CameraAnimation>,
+    view_scaling: ViewScaling,
+    background: Background,
+    is_minimap_visible: bool,
+    // Buttons pressed on the minimap. Their motion and release don't reach the board.
+    minimap_presses: Vec<MouseButton>,
+    // Named views to jump between
+    bookmarks: BTreeMap<String, Camera>,
+    // Where bookmarks are kept between runs, if anywhere
+    bookmarks_path: Option<PathBuf>,
+    frames: BTreeMap<String, Frame>,
+    current_slide: Option<String>,
+    // Hide everything outside the current slide
+    is_slide_clipping: bool,
+    // Last view sent out in a ViewChanged event
+    published_camera: Option<Camera>,
+    // Remote views from this peer drive our camera
+    followed_peer: Option<String>,
+    // Drawn in name order, so later names end up on top
+    viewports: BTreeMap<String, Viewport>,
+    // commands from the network thread
+    command_rx: mpsc::Receiver<NetMessage>,
+    // replies to req-reply commands, sent back to the network thread
+    reply_socket: zmq::Socket,
+    net_stats: Arc<NetStats>,
+    // events from this canvas
+    pub_socket: zmq::Socket,
+}
 
-        let pipeline = ctx.new_pipeline(
-            &[BufferLayout::default()],
-            &[
-                VertexAttribute::new("in_pos", VertexFormat::Float2),
-                VertexAttribute::new("in_color", VertexFormat::Float4),
-                VertexAttribute::new("in_uv", VertexFormat::Float2),
-            ],
-            shader,
-            params,
-        );
+impl Stage {
+    pub fn new(view_scaling: ViewScaling, bookmarks_path: Option<PathBuf>) -> Stage {
+        let renderer = Renderer::new();
 
         // Polygons must have counter-clockwise orientation
 
@@ -265,127 +2077,981 @@ impl Stage {
         sub_socket.set_ipv6(true).unwrap();
         sub_socket.set_subscribe(b"").unwrap();
         sub_socket.bind("tcp://*:9466").unwrap();
+        let reply_socket = zmq_ctx.socket(zmq::PAIR).unwrap();
+        reply_socket.bind("inproc://replies").unwrap();
+        let net_reply_socket = zmq_ctx.socket(zmq::PAIR).unwrap();
+        net_reply_socket.connect("inproc://replies").unwrap();
+
+        // The req and sub sockets are only touched by the network thread
+        let (command_tx, command_rx) = mpsc::sync_channel(COMMAND_QUEUE_LEN);
+        let net_stats = Arc::new(NetStats::default());
+        let stats = net_stats.clone();
+        thread::spawn(move || {
+            run_network(req_socket, sub_socket, net_reply_socket, command_tx, stats)
+        });
+
+        let mut stage = Stage {
+            renderer,
+            camera: Camera::new(),
+            camera_anim: None,
+            view_scaling,
+            background: Background::new(),
+            is_minimap_visible: false,
+            minimap_presses: vec![],
+            bookmarks: BTreeMap::new(),
+            bookmarks_path,
+            frames: BTreeMap::new(),
+            current_slide: None,
+            is_slide_clipping: false,
+            published_camera: None,
+            followed_peer: None,
+            viewports: BTreeMap::new(),
+            layers: BTreeMap::new(),
+            command_rx,
+            reply_socket,
+            net_stats,
+            pub_socket,
+        };
+        //stage.layers.insert("box1".to_string(), layer1);
+        //stage.layers.insert("box2".to_string(), layer2);
+        #[rustfmt::skip]
+        stage.draw_line(
+            "origin".to_string(),
+            -0.1, 0., 0.1, 0.,
+            0.001,
+            1., 0., 0., 0.4,
+        );
+        #[rustfmt::skip]
+        stage.draw_line(
+            "origin".to_string(),
+            0., 0.1, 0., -0.1,
+            0.001,
+            1., 0., 0., 0.4,
+        );
+        stage.read_bookmarks();
+        stage
+    }
+
+    #[rustfmt::skip]
+    fn draw_line(
+        &mut self, layer_name: String,
+        x1: f32, y1: f32, x2: f32, y2: f32,
+        thickness: f32,
+        r: f32, g: f32, b: f32, a: f32,
+    ) {
+        //debug!(
+        //    "draw_line({}, {}, {}, {}, {}, {}, {}, {}, {}, {})",
+        //    layer_name, x1, y1, x2, y2, thickness, r, g, b, a
+        //);
+        // NaN fails this too. Otherwise it would end up in the bounds used
+        // to fit the camera and draw the minimap.
+        if ![x1, y1, x2, y2, thickness]
+            .iter()
+            .all(|val| val.abs() <= MAX_LINE_COORD)
+        {
+            warn!("draw_line() rejected: coordinates out of range");
+            return;
+        }
+        let line = Line {
+            start: glam::vec2(x1, y1),
+            end: glam::vec2(x2, y2),
+            thickness,
+            color: [r, g, b, a],
+        };
+        let mesh = line.mesh();
+        if mesh.0.is_empty() {
+            return;
+        }
+        let layer = self.layer_mut(layer_name);
+        layer.push_line(line, mesh);
+        layer.strokes += 1;
+    }
+
+    fn create_parent_layers(&mut self, layer_name: &str) {
+        let mut parent = parent_layer_name(layer_name);
+        while let Some(parent_name) = parent {
+            if !self.layers.contains_key(parent_name) {
+                self.layers.insert(parent_name.to_string(), Layer::new());
+            }
+            parent = parent_layer_name(parent_name);
+        }
+    }
+
+    // Fetch a layer, creating it along with any missing parent groups
+    fn layer_mut(&mut self, layer_name: String) -> &mut Layer {
+        self.create_parent_layers(&layer_name);
+        let is_chunked = !self.is_screen_space(&layer_name);
+        self.layers.entry(layer_name).or_insert_with(|| Layer {
+            is_chunked,
+            ..Layer::new()
+        })
+    }
+
+    // Is the layer or any group it's in an overlay, whether visible or not
+    fn is_screen_space(&self, layer_name: &str) -> bool {
+        let mut name = Some(layer_name);
+        while let Some(layer_name) = name {
+            if let Some(layer) = self.layers.get(layer_name) {
+                if layer.is_screen_space {
+                    return true;
+                }
+            }
+            name = parent_layer_name(layer_name);
+        }
+        false
+    }
+
+    // Call after anything which could move layers in or out of an overlay
+    fn update_chunking(&mut self) {
+        let names: Vec<String> = self.layers.keys().cloned().collect();
+        for name in names {
+            let is_chunked = !self.is_screen_space(&name);
+            self.layers.get_mut(&name).unwrap().set_chunked(is_chunked);
+        }
+    }
+
+    // None means the layer or one of its parents is hidden
+    fn resolve_layer(&self, layer_name: &str) -> Option<ResolvedLayer> {
+        let layer = self.layers.get(layer_name)?;
+        if layer.is_hidden {
+            return None;
+        }
+        let parent = match parent_layer_name(layer_name) {
+            Some(parent_name) => self.resolve_layer(parent_name)?,
+            None => ResolvedLayer {
+                model: glam::Mat4::IDENTITY,
+                opacity: 1.,
+                is_screen_space: false,
+            },
+        };
+        Some(ResolvedLayer {
+            model: parent.model * layer.model,
+            opacity: parent.opacity * layer.opacity,
+            is_screen_space: parent.is_screen_space || layer.is_screen_space,
+        })
+    }
+
+    // Same as resolve_layer() but ignores visibility. Missing layers act as identity.
+    fn world_model(&self, layer_name: &str) -> glam::Mat4 {
+        let parent_model = match parent_layer_name(layer_name) {
+            Some(parent_name) => self.world_model(parent_name),
+            None => glam::Mat4::IDENTITY,
+        };
+        match self.layers.get(layer_name) {
+            Some(layer) => parent_model * layer.model,
+            None => parent_model,
+        }
+    }
+
+    fn layer_world_bounds(&self, layer_name: &str) -> Option<Bounds> {
+        let bounds = self.layers.get(layer_name)?.local_bounds()?;
+        Some(transform_bounds(self.world_model(layer_name), bounds))
+    }
+
+    // Bounds of everything inside a layer, visible or not. Overlays are in
+    // pixels rather than world units so they're left out.
+    fn subtree_world_bounds(&self, root: &str) -> Option<Bounds> {
+        self.layers
+            .keys()
+            .filter(|name| is_in_subtree(name, root) && !self.is_screen_space(name))
+            .filter_map(|name| self.layer_world_bounds(name))
+            .reduce(union_bounds)
+    }
+
+    // Bounds of everything currently visible on the board, ignoring overlays
+    fn visible_content_bounds(&self) -> Option<Bounds> {
+        self.visible_layer_bounds().into_iter().reduce(union_bounds)
+    }
+
+    // World bounds of each visible layer with content, skipping overlays
+    fn visible_layer_bounds(&self) -> Vec<Bounds> {
+        self.layers
+            .iter()
+            .filter_map(|(name, layer)| {
+                let resolved = self.resolve_layer(name)?;
+                if resolved.is_screen_space {
+                    return None;
+                }
+                Some(transform_bounds(resolved.model, layer.local_bounds()?))
+            })
+            .collect()
+    }
+
+    // Locks cascade down from parent groups, and apply to layers which
+    // don't exist yet so nothing new can be added inside a locked group.
+    fn is_layer_locked(&self, layer_name: &str) -> bool {
+        let mut name = Some(layer_name);
+        while let Some(layer_name) = name {
+            if let Some(layer) = self.layers.get(layer_name) {
+                if layer.is_locked {
+                    return true;
+                }
+            }
+            name = parent_layer_name(layer_name);
+        }
+        false
+    }
+
+    // Is anything in this subtree locked, including the root's parents?
+    fn is_subtree_locked(&self, root: &str) -> bool {
+        self.is_layer_locked(root)
+            || self
+                .layers
+                .iter()
+                .any(|(name, layer)| layer.is_locked && is_in_subtree(name, root))
+    }
+
+    // Moves a layer along with everything inside it to a new path
+    fn rename_layer(&mut self, old_name: &str, new_name: &str) -> EditStatus {
+        if !self.layers.contains_key(old_name)
+            || self.layers.contains_key(new_name)
+            || is_in_subtree(new_name, old_name)
+        {
+            return EditStatus::Failed;
+        }
+        if self.is_subtree_locked(old_name) || self.is_layer_locked(new_name) {
+            warn!(
+                "rename_layer({}, {}) rejected: layer is locked",
+                old_name, new_name
+            );
+            return EditStatus::Locked;
+        }
+
+        let subtree: Vec<String> = self
+            .layers
+            .keys()
+            .filter(|name| is_in_subtree(name, old_name))
+            .cloned()
+            .collect();
+        for name in subtree {
+            let layer = self.layers.remove(&name).unwrap();
+            let new_path = format!("{}{}", new_name, &name[old_name.len()..]);
+            self.layers.insert(new_path, layer);
+        }
+        self.create_parent_layers(new_name);
+        self.update_chunking();
+        EditStatus::Ok
+    }
+
+    // Deep copies a layer along with everything inside it.
+    // The copies start unlocked so they can be edited freely.
+    fn clone_layer(&mut self, src_name: &str, dst_name: &str) -> EditStatus {
+        if !self.layers.contains_key(src_name) || self.layers.contains_key(dst_name) {
+            return EditStatus::Failed;
+        }
+        if self.is_layer_locked(dst_name) {
+            warn!(
+                "clone_layer({}, {}) rejected: layer is locked",
+                src_name, dst_name
+            );
+            return EditStatus::Locked;
+        }
+
+        let copies: Vec<(String, Layer)> = self
+            .layers
+            .iter()
+            .filter(|(name, _)| is_in_subtree(name, src_name))
+            .map(|(name, layer)| {
+                let mut layer = layer.clone();
+                layer.is_locked = false;
+                layer.owner = None;
+                (format!("{}{}", dst_name, &name[src_name.len()..]), layer)
+            })
+            .collect();
+        self.layers.extend(copies);
+        self.create_parent_layers(dst_name);
+        self.update_chunking();
+        EditStatus::Ok
+    }
+
+    // Flattens the sources into the destination layer. Each source's model is
+    // baked into its strokes so nothing moves on screen, then the
+    // sources (and anything inside them) are deleted.
+    fn merge_layers(&mut self, src_names: &[String], dst_name: String) -> EditStatus {
+        let is_inside_src =
+            |layer_name: &str| src_names.iter().any(|src| is_in_subtree(layer_name, src));
+
+        for src_name in src_names {
+            if !self.layers.contains_key(src_name) || is_in_subtree(&dst_name, src_name) {
+                return EditStatus::Failed;
+            }
+            if self.is_subtree_locked(src_name) {
+                warn!("merge_layers() rejected: layer {} is locked", src_name);
+                return EditStatus::Locked;
+            }
+        }
+        if self.is_layer_locked(&dst_name) {
+            warn!("merge_layers() rejected: layer {} is locked", dst_name);
+            return EditStatus::Locked;
+        }
+
+        let dst_model_inv = self.world_model(&dst_name).inverse();
+        let mut lines = vec![];
+        let mut strokes = 0;
+        for (name, layer) in &self.layers {
+            if !is_inside_src(name) {
+                continue;
+            }
+            let transform = dst_model_inv * self.world_model(name);
+            for chunk in &layer.chunks {
+                lines.extend(chunk.lines.iter().map(|line| line.transform(transform)));
+            }
+            strokes += layer.strokes;
+        }
+
+        self.layers.retain(|name, _| !is_inside_src(name));
+        let dst = self.layer_mut(dst_name);
+        for line in lines {
+            let mesh = line.mesh();
+            dst.push_line(line, mesh);
+        }
+        dst.strokes += strokes;
+        EditStatus::Ok
+    }
+
+    // Changes a single layer, unless it's locked. Edits which pass down to
+    // children are also blocked by anything locked inside the layer.
+    // Visibility isn't an edit since hiding a template layer shouldn't need
+    // its owner.
+    fn edit_layer(
+        &mut self,
+        layer_name: &str,
+        is_inherited: bool,
+        edit: impl FnOnce(&mut Layer),
+    ) -> EditStatus {
+        let is_locked = if is_inherited {
+            self.is_subtree_locked(layer_name)
+        } else {
+            self.is_layer_locked(layer_name)
+        };
+        if is_locked {
+            warn!("edit of layer {} rejected: layer is locked", layer_name);
+            return EditStatus::Locked;
+        }
+        match self.layers.get_mut(layer_name) {
+            Some(layer) => {
+                edit(layer);
+                EditStatus::Ok
+            }
+            None => EditStatus::Failed,
+        }
+    }
+
+    fn layer_info(&self, layer_name: &str) -> Vec<u8> {
+        let layer = &self.layers[layer_name];
+        let mut info = vec![];
+
+        layer.is_hidden.encode(&mut info).unwrap();
+        let is_visible = self.resolve_layer(layer_name).is_some();
+        is_visible.encode(&mut info).unwrap();
+        layer.opacity.encode(&mut info).unwrap();
+        for val in layer.model.to_cols_array() {
+            val.encode(&mut info).unwrap();
+        }
+
+        (layer.vert_count() as u64).encode(&mut info).unwrap();
+        (layer.face_count() as u64).encode(&mut info).unwrap();
+        layer.strokes.encode(&mut info).unwrap();
+
+        // World space bounds of the layer and everything inside it, if any
+        match self.subtree_world_bounds(layer_name) {
+            Some((min, max)) => {
+                true.encode(&mut info).unwrap();
+                min.x.encode(&mut info).unwrap();
+                min.y.encode(&mut info).unwrap();
+                max.x.encode(&mut info).unwrap();
+                max.y.encode(&mut info).unwrap();
+            }
+            None => {
+                false.encode(&mut info).unwrap();
+            }
+        }
+
+        layer.is_locked.encode(&mut info).unwrap();
+        // Empty when nobody owns the layer
+        let owner = layer.owner.as_deref().unwrap_or("");
+        owner.encode(&mut info).unwrap();
+
+        VarInt(layer.metadata.len() as u64)
+            .encode(&mut info)
+            .unwrap();
+        for (key, value) in &layer.metadata {
+            key.encode(&mut info).unwrap();
+            value.encode(&mut info).unwrap();
+        }
+
+        layer.is_screen_space.encode(&mut info).unwrap();
+        info
+    }
+
+    // Moves the board by (x, y) along the screen axes
+    fn pan(&mut self, x: f32, y: f32) {
+        debug!("pan({}, {})", x, y);
+        let offset = glam::Vec2::from_angle(-self.camera.rotation).rotate(glam::vec2(x, y));
+        self.camera.center -= offset;
+    }
+    // Scales around the world origin
+    fn zoom(&mut self, scale: f32) {
+        debug!("zoom({})", scale);
+        self.camera.zoom_at(scale, glam::Vec2::ZERO);
+    }
+
+    // Move the camera over duration seconds, or instantly if it's zero.
+    // NaN would never finish animating, so it jumps straight there too.
+    fn animate_camera(&mut self, to: Camera, duration: f32) {
+        // Also called from input events, which don't redraw by themselves
+        self.mark_dirty();
+        if duration.is_nan() || duration <= 0. {
+            self.camera = to;
+            self.camera_anim = None;
+            return;
+        }
+        self.camera_anim = Some(CameraAnimation {
+            from: self.camera,
+            to,
+            start: time::Instant::now(),
+            duration: duration.min(MAX_ANIMATION_DURATION),
+        });
+    }
+
+    // Bookmarks as listed by ListBookmarks, which is also the --bookmarks file format
+    fn encode_bookmarks(bookmarks: &BTreeMap<String, Camera>) -> Vec<u8> {
+        let mut data = vec![];
+        VarInt(bookmarks.len() as u64).encode(&mut data).unwrap();
+        for (name, camera) in bookmarks {
+            name.encode(&mut data).unwrap();
+            camera.center.x.encode(&mut data).unwrap();
+            camera.center.y.encode(&mut data).unwrap();
+            camera.zoom.encode(&mut data).unwrap();
+            camera.rotation.encode(&mut data).unwrap();
+        }
+        data
+    }
+
+    fn decode_bookmarks(data: &[u8]) -> std::io::Result<BTreeMap<String, Camera>> {
+        let mut cur = Cursor::new(data);
+        let len = VarInt::decode(&mut cur)?.0;
+        let mut bookmarks = BTreeMap::new();
+        for _ in 0..len {
+            let name = String::decode(&mut cur)?;
+            let x = f32::decode(&mut cur)?;
+            let y = f32::decode(&mut cur)?;
+            let zoom = f32::decode(&mut cur)?;
+            let rotation = f32::decode(&mut cur)?;
+            match Camera::from_view(x, y, zoom, rotation) {
+                Some(camera) => {
+                    bookmarks.insert(name, camera);
+                }
+                None => warn!("Skipping bookmark {} with an invalid view", name),
+            }
+        }
+        Ok(bookmarks)
+    }
+
+    fn read_bookmarks(&mut self) {
+        let Some(path) = &self.bookmarks_path else {
+            return;
+        };
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            // Nothing saved yet
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return,
+            Err(err) => {
+                warn!("Couldn't read bookmarks from {}: {}", path.display(), err);
+                return;
+            }
+        };
+        match Self::decode_bookmarks(&data) {
+            Ok(bookmarks) => self.bookmarks = bookmarks,
+            Err(err) => warn!("Bad bookmarks file {}: {}", path.display(), err),
+        }
+    }
+
+    fn write_bookmarks(&self) {
+        let Some(path) = &self.bookmarks_path else {
+            return;
+        };
+        if let Err(err) = fs::write(path, Self::encode_bookmarks(&self.bookmarks)) {
+            warn!("Couldn't write bookmarks to {}: {}", path.display(), err);
+        }
+    }
+
+    // Let peers follow along whenever the view moves
+    fn publish_view(&mut self) {
+        if self.published_camera == Some(self.camera) {
+            return;
+        }
+        self.published_camera = Some(self.camera);
+
+        let mut event = vec![];
+        (PubEvents::ViewChanged as u8).encode(&mut event).unwrap();
+        self.camera.center.x.encode(&mut event).unwrap();
+        self.camera.center.y.encode(&mut event).unwrap();
+        self.camera.zoom.encode(&mut event).unwrap();
+        self.camera.rotation.encode(&mut event).unwrap();
+        //debug!("ViewChanged => {:?}", self.camera);
+        self.pub_socket.send(event, 0).unwrap();
+    }
+
+    fn update_camera_animation(&mut self) {
+        let Some(anim) = &self.camera_anim else {
+            return;
+        };
+        let now = time::Instant::now();
+        self.camera = anim.camera_at(now);
+        if anim.is_finished(now) {
+            self.camera_anim = None;
+        }
+        self.mark_dirty();
+    }
+
+    fn apply_command(&mut self, msg: NetMessage) {
+        self.net_stats.queued.fetch_sub(1, Ordering::Relaxed);
+        match msg {
+            NetMessage::Req(req) => {
+                let reply = self.process_req(req);
+                self.reply_socket.send(reply, 0).unwrap();
+            }
+            NetMessage::Sub(sub) => self.process_sub(sub),
+        }
+    }
+
+    // Something on screen changed, so wake the event loop up to draw it
+    fn mark_dirty(&self) {
+        window::schedule_update();
+    }
+
+    // View space to clip space
+    fn screen_scale(&self) -> glam::Vec2 {
+        // Framebuffer size in physical pixels
+        let (screen_width, screen_height) = window::screen_size();
+        let pixels_per_unit = match self.view_scaling {
+            ViewScaling::Legacy => PIXELS_PER_UNIT,
+            ViewScaling::Dpi => PIXELS_PER_UNIT * window::dpi_scale(),
+        };
+        // Clip space is 2 wide
+        2. * pixels_per_unit / glam::vec2(screen_width, screen_height)
+    }
+
+    fn calc_proj_matrix(&self) -> glam::Mat4 {
+        self.calc_camera_proj_matrix(&self.camera, ScreenRect::full_screen())
+    }
+
+    // Projection for a camera centred on rect. Done with the matrix rather than
+    // apply_viewport() since the metal backend ignores viewports.
+    fn calc_camera_proj_matrix(&self, camera: &Camera, rect: ScreenRect) -> glam::Mat4 {
+        let (screen_width, screen_height) = window::screen_size();
+        let center_x = 2. * (rect.x + rect.w / 2.) / screen_width - 1.;
+        let center_y = 1. - 2. * (rect.y + rect.h / 2.) / screen_height;
+        glam::Mat4::from_translation(glam::vec3(center_x, center_y, 0.))
+            * glam::Mat4::from_scale(self.screen_scale().extend(1.))
+            * camera.view_matrix()
+    }
+
+    // How much detail a layer with this world model needs through camera
+    fn lod_level(&self, camera: &Camera, model: glam::Mat4) -> usize {
+        let (screen_width, _) = window::screen_size();
+        // Physical pixels per layer unit
+        let pixels_per_unit =
+            camera.zoom * model_scale(model) * self.screen_scale().x * screen_width / 2.;
+        lod_level(1. / pixels_per_unit)
+    }
+
+    // Topmost viewport under a position in pixels
+    fn viewport_at(&self, x: f32, y: f32) -> Option<&Viewport> {
+        self.viewports
+            .values()
+            .rev()
+            .find(|viewport| viewport.screen_rect().contains(x, y))
+    }
+
+    // Projection of whichever view is under a position in pixels
+    fn proj_at(&self, x: f32, y: f32) -> glam::Mat4 {
+        match self.viewport_at(x, y) {
+            Some(viewport) => {
+                self.calc_camera_proj_matrix(&viewport.camera, viewport.screen_rect())
+            }
+            None => self.calc_proj_matrix(),
+        }
+    }
+
+    // Mouse position in pixels to world space, through whichever view is under it
+    fn mouse_to_world(&self, x: f32, y: f32) -> (f32, f32) {
+        let (screen_width, screen_height) = window::screen_size();
+        self.screen_to_world(x / screen_width, y / screen_height)
+    }
+
+    // A camera showing all of bounds with the current rotation.
+    // Padding is the fraction of the screen to leave empty around each side.
+    fn fit_camera(&self, bounds: Bounds, padding: f32) -> Camera {
+        let rotation = self.camera.rotation;
+        // Fit in view space, where the screen is axis aligned
+        let (min, max) = transform_bounds(glam::Mat4::from_rotation_z(rotation), bounds);
+        let center = glam::Vec2::from_angle(-rotation).rotate((min + max) / 2.);
+        let size = max - min;
+
+        // Clip space is 2 wide, so this is how much of it the content can use
+        let usable = 2. * (1. - 2. * padding.clamp(0., 0.45));
+        let zoom = (usable / (self.screen_scale() * size)).min_element();
+
+        Camera {
+            center,
+            // Infinite when there's nothing but a single point
+            zoom: zoom.clamp(MIN_ZOOM, MAX_ZOOM),
+            rotation,
+        }
+    }
+
+    // Screen here refers to (0, 1). Like the mouse, this goes through
+    // whichever view is under the position.
+    fn screen_to_world(&self, x: f32, y: f32) -> (f32, f32) {
+        let (screen_width, screen_height) = window::screen_size();
+        let proj = self.proj_at(x * screen_width, y * screen_height);
+        let pos = glam::vec4(2. * x - 1., 1. - 2. * y, 0., 1.);
+        let world_pos = (proj.inverse() * pos).xy();
+        (world_pos.x, world_pos.y)
+    }
+
+    // Screen pixels with the origin at the top left, same as mouse events
+    fn calc_screen_proj_matrix(&self) -> glam::Mat4 {
+        let (screen_width, screen_height) = window::screen_size();
+        glam::Mat4::orthographic_rh_gl(0., screen_width, screen_height, 0., -1., 1.)
+    }
+
+    // World space area covered by the screen
+    fn view_world_bounds(&self) -> Bounds {
+        let proj_inv = self.calc_proj_matrix().inverse();
+        transform_bounds(proj_inv, (glam::vec2(-1., -1.), glam::vec2(1., 1.)))
+    }
+
+    // World space area a camera shows inside rect
+    fn camera_world_bounds(&self, camera: &Camera, rect: ScreenRect) -> Bounds {
+        let (screen_width, screen_height) = window::screen_size();
+        let proj_inv = self.calc_camera_proj_matrix(camera, rect).inverse();
+        let min = glam::vec2(
+            2. * rect.x / screen_width - 1.,
+            1. - 2. * (rect.y + rect.h) / screen_height,
+        );
+        let max = glam::vec2(
+            2. * (rect.x + rect.w) / screen_width - 1.,
+            1. - 2. * rect.y / screen_height,
+        );
+        transform_bounds(proj_inv, (min, max))
+    }
+
+    fn background_mesh(&self, camera: &Camera, rect: ScreenRect) -> (Vec<Vertex>, Vec<Face>) {
+        let mut verts = vec![];
+        let mut faces = vec![];
+        let background = &self.background;
+        if background.mode == BackgroundMode::Plain {
+            return (verts, faces);
+        }
+
+        let bounds = self.camera_world_bounds(camera, rect);
+        let (screen_width, _) = window::screen_size();
+        let dpi_scale = window::dpi_scale();
+        // Physical pixels per world unit
+        let pixels_per_unit = camera.zoom * self.screen_scale().x * screen_width / 2.;
+
+        // Step the spacing up or down so the pattern keeps roughly the same density on screen
+        let min_spacing = MIN_GRID_PIXELS * dpi_scale / pixels_per_unit;
+        let level = (min_spacing / background.spacing).log(GRID_STEP).ceil();
+        let spacing = background.spacing * GRID_STEP.powf(level);
+        // One logical pixel wide
+        let thickness = dpi_scale / pixels_per_unit;
+        let color = background.line_color;
+
+        use std::f32::consts::FRAC_PI_2;
+        #[rustfmt::skip]
+        match background.mode {
+            BackgroundMode::Plain => {}
+            BackgroundMode::Grid => {
+                parallel_lines(bounds, 0., spacing, thickness, color, &mut verts, &mut faces);
+                parallel_lines(bounds, FRAC_PI_2, spacing, thickness, color, &mut verts, &mut faces);
+            }
+            BackgroundMode::Ruled => {
+                parallel_lines(bounds, 0., spacing, thickness, color, &mut verts, &mut faces);
+            }
+            BackgroundMode::Isometric => {
+                // Triangles with sides of length spacing
+                let row_spacing = spacing * 3f32.sqrt() / 2.;
+                for angle in [FRAC_PI_2 / 3., FRAC_PI_2, 5. * FRAC_PI_2 / 3.] {
+                    parallel_lines(bounds, angle, row_spacing, thickness, color, &mut verts, &mut faces);
+                }
+            }
+            BackgroundMode::Dots => {
+                let (min, max) = bounds;
+                let radius = thickness;
+                for i in (min.x / spacing).ceil() as i64..=(max.x / spacing).floor() as i64 {
+                    for j in (min.y / spacing).ceil() as i64..=(max.y / spacing).floor() as i64 {
+                        let (x, y) = (i as f32 * spacing, j as f32 * spacing);
+                        // A tiny square
+                        let (dot_verts, dot_faces) = draw_line(x - radius, y, x + radius, y, 2. * radius, color);
+                        push_mesh(&mut verts, &mut faces, dot_verts, dot_faces);
+                    }
+                }
+            }
+        };
+        (verts, faces)
+    }
+
+    // Inverse of screen_to_world(). Viewports cover the main view, so
+    // points shown in one are reported where the viewport shows them.
+    fn world_to_screen(&self, x: f32, y: f32) -> (f32, f32) {
+        let (screen_width, screen_height) = window::screen_size();
+        let project = |proj: glam::Mat4| {
+            let pos = proj * glam::vec4(x, y, 0., 1.);
+            ((pos.x + 1.) / 2., (1. - pos.y) / 2.)
+        };
+        for viewport in self.viewports.values().rev() {
+            let (screen_x, screen_y) =
+                project(self.calc_camera_proj_matrix(&viewport.camera, viewport.screen_rect()));
+            // Unless a viewport on top of this one hides it
+            let top = self.viewport_at(screen_x * screen_width, screen_y * screen_height);
+            if top.is_some_and(|top| std::ptr::eq(top, viewport)) {
+                return (screen_x, screen_y);
+            }
+        }
+        project(self.calc_proj_matrix())
+    }
+
+    // Frame names in presentation order
+    fn slide_names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.frames.keys().cloned().collect();
+        names.sort_by_key(|name| self.frames[name].order);
+        names
+    }
+
+    fn goto_slide(&mut self, name: &str, duration: f32) -> bool {
+        let Some(frame) = self.frames.get(name) else {
+            return false;
+        };
+        let camera = self.fit_camera(frame.bounds, 0.);
+        self.animate_camera(camera, duration);
+        self.current_slide = Some(name.to_string());
+
+        let slide_names = self.slide_names();
+        let index = slide_names.iter().position(|slide| slide == name).unwrap();
+        let mut event = vec![];
+        (PubEvents::SlideChanged as u8).encode(&mut event).unwrap();
+        name.to_string().encode(&mut event).unwrap();
+        (index as u32).encode(&mut event).unwrap();
+        (slide_names.len() as u32).encode(&mut event).unwrap();
+        debug!(
+            "SlideChanged => ({}, {}/{})",
+            name,
+            index,
+            slide_names.len()
+        );
+        self.pub_socket.send(event, 0).unwrap();
+        true
+    }
 
-        let mut stage = Stage {
-            ctx,
-            pipeline,
-            white_texture,
-            proj: glam::Mat4::IDENTITY,
-            layers: HashMap::new(),
-            req_socket,
-            pub_socket,
-            sub_socket
+    // Step through the slides. With no current slide, starts from either end.
+    fn step_slide(&mut self, is_forward: bool, duration: f32) -> bool {
+        let slide_names = self.slide_names();
+        let current = self
+            .current_slide
+            .as_ref()
+            .and_then(|name| slide_names.iter().position(|slide| slide == name));
+        let next = match (current, is_forward) {
+            (Some(index), true) => index + 1,
+            (Some(index), false) => match index.checked_sub(1) {
+                Some(index) => index,
+                None => return false,
+            },
+            (None, true) => 0,
+            (None, false) => match slide_names.len().checked_sub(1) {
+                Some(index) => index,
+                None => return false,
+            },
         };
-        //stage.layers.insert("box1".to_string(), layer1);
-        //stage.layers.insert("box2".to_string(), layer2);
-        #[rustfmt::skip]
-        stage.draw_line(
-            "origin".to_string(),
-            -0.1, 0., 0.1, 0.,
-            0.001,
-            1., 0., 0., 0.4,
+        match slide_names.get(next) {
+            Some(name) => self.goto_slide(name, duration),
+            None => false,
+        }
+    }
+
+    // Area of the screen covered by the current slide
+    fn slide_clip_rect(&self) -> Option<ScreenRect> {
+        let frame = self.frames.get(self.current_slide.as_ref()?)?;
+        let (screen_width, screen_height) = window::screen_size();
+        let proj = self.calc_proj_matrix();
+        let (min, max) = frame.bounds;
+        // Rotated frames get clipped to their bounding box on screen
+        let (min, max) = [min, glam::vec2(max.x, min.y), max, glam::vec2(min.x, max.y)]
+            .into_iter()
+            .map(|corner| {
+                // Slides belong to the main view, whatever viewports cover it
+                let pos = proj * corner.extend(0.).extend(1.);
+                glam::vec2(
+                    (pos.x + 1.) / 2. * screen_width,
+                    (1. - pos.y) / 2. * screen_height,
+                )
+            })
+            .fold((glam::Vec2::MAX, glam::Vec2::MIN), |(min, max), pos| {
+                (min.min(pos), max.max(pos))
+            });
+        // Keep within the screen
+        let screen_size = glam::vec2(screen_width, screen_height);
+        let (min, max) = (
+            min.clamp(glam::Vec2::ZERO, screen_size),
+            max.clamp(glam::Vec2::ZERO, screen_size),
         );
+        Some(ScreenRect {
+            x: min.x,
+            y: min.y,
+            w: max.x - min.x,
+            h: max.y - min.y,
+        })
+    }
+
+    // Background colour filling rect, with a border around it
+    fn viewport_frame_mesh(&self, rect: ScreenRect) -> (Vec<Vertex>, Vec<Face>) {
+        let mut verts = vec![];
+        let mut faces = vec![];
+        let y = rect.y + rect.h / 2.;
         #[rustfmt::skip]
-        stage.draw_line(
-            "origin".to_string(),
-            0., 0.1, 0., -0.1,
-            0.001,
-            1., 0., 0., 0.4,
+        let (fill_verts, fill_faces) =
+            draw_line(rect.x, y, rect.x + rect.w, y, rect.h, self.background.color);
+        push_mesh(&mut verts, &mut faces, fill_verts, fill_faces);
+
+        let (min, max) = (
+            glam::vec2(rect.x, rect.y),
+            glam::vec2(rect.x + rect.w, rect.y + rect.h),
         );
-        stage
+        let corners = [min, glam::vec2(max.x, min.y), max, glam::vec2(min.x, max.y)];
+        #[rustfmt::skip]
+        draw_outline(&corners, window::dpi_scale(), self.background.line_color, &mut verts, &mut faces);
+        (verts, faces)
     }
 
-    #[rustfmt::skip]
-    fn draw_line(
-        &mut self, layer_name: String,
-        x1: f32, y1: f32, x2: f32, y2: f32,
-        thickness: f32,
-        r: f32, g: f32, b: f32, a: f32,
-    ) {
-        //debug!(
-        //    "draw_line({}, {}, {}, {}, {}, {}, {}, {}, {}, {})",
-        //    layer_name, x1, y1, x2, y2, thickness, r, g, b, a
-        //);
-        let color = [r, g, b, a];
-        let (mut verts, mut faces) = draw_line(x1, y1, x2, y2, thickness, color);
-        let layer = self.layers.entry(layer_name).or_insert_with(Layer::new);
-        let offset = layer.verts.len() as u32;
-        layer.verts.append(&mut verts);
-        for face in &mut faces {
-            for idx in &mut face.idxs {
-                *idx += offset;
-            }
+    fn minimap_rect(&self) -> ScreenRect {
+        let (screen_width, screen_height) = window::screen_size();
+        let dpi_scale = window::dpi_scale();
+        let size = MINIMAP_SIZE * dpi_scale;
+        let margin = MINIMAP_MARGIN * dpi_scale;
+        ScreenRect {
+            x: screen_width - size - margin,
+            y: screen_height - size - margin,
+            w: size,
+            h: size,
         }
-        layer.faces.append(&mut faces);
     }
 
-    fn pan(&mut self, x: f32, y: f32) {
-        debug!("pan({}, {})", x, y);
-        self.proj *= glam::Mat4::from_translation(glam::Vec3::new(x, y, 0.));
+    // Square world area shown in the minimap, covering all content and the view
+    fn minimap_world_bounds(&self) -> Bounds {
+        let view = self.view_world_bounds();
+        let (min, max) = match self.visible_content_bounds() {
+            Some(content) => union_bounds(content, view),
+            None => view,
+        };
+        let center = (min + max) / 2.;
+        // Leave a little room around the edges
+        let half_size = 1.1 * (max - min).max_element() / 2.;
+        (center - half_size, center + half_size)
     }
-    fn zoom(&mut self, scale: f32) {
-        debug!("zoom({})", scale);
-        self.proj *= glam::Mat4::from_scale(glam::Vec3::new(scale, scale, 1.));
+
+    // World space onto the minimap's corner of the screen
+    fn calc_minimap_proj_matrix(&self) -> glam::Mat4 {
+        let rect = self.minimap_rect();
+        let (min, max) = self.minimap_world_bounds();
+        let size = max - min;
+        self.calc_screen_proj_matrix()
+            * glam::Mat4::from_translation(glam::vec3(rect.x, rect.y, 0.))
+            * glam::Mat4::from_scale(glam::vec3(rect.w / size.x, -rect.h / size.y, 1.))
+            * glam::Mat4::from_translation(glam::vec3(-min.x, -max.y, 0.))
     }
 
-    fn calc_proj_matrix(&self) -> glam::Mat4 {
+    // Screen pixels inside the minimap to world space
+    fn minimap_to_world(&self, x: f32, y: f32) -> glam::Vec2 {
         let (screen_width, screen_height) = window::screen_size();
-        // Preserve the same size irregardless of the screen size
-        self.proj
-            * glam::Mat4::from_scale(glam::Vec3::new(
-                2500. / screen_width,
-                2500. / screen_height,
-                1.,
-            ))
+        let x = 2. * x / screen_width - 1.;
+        let y = 1. - 2. * y / screen_height;
+        let proj_inv = self.calc_minimap_proj_matrix().inverse();
+        (proj_inv * glam::vec4(x, y, 0., 1.)).xy()
     }
 
-    // Screen here refers to (0, 1)
-    fn screen_to_world(&self, x: f32, y: f32) -> (f32, f32) {
-        let x = 2. * x - 1.;
-        let y = 1. - 2. * y;
-        let pos = glam::vec4(x, y, 0., 1.);
+    fn minimap_mesh(&self) -> (Vec<Vertex>, Vec<Face>) {
+        let mut verts = vec![];
+        let mut faces = vec![];
+        let (min, max) = self.minimap_world_bounds();
+        // One logical pixel wide
+        let thickness = window::dpi_scale() * (max.x - min.x) / self.minimap_rect().w;
+
+        // Backdrop so the minimap stands out from the board behind it
+        let y = (min.y + max.y) / 2.;
+        #[rustfmt::skip]
+        let (backdrop_verts, backdrop_faces) =
+            draw_line(min.x, y, max.x, y, max.y - min.y, [0.1, 0.1, 0.1, 0.8]);
+        push_mesh(&mut verts, &mut faces, backdrop_verts, backdrop_faces);
+
+        for (min, max) in self.visible_layer_bounds() {
+            let corners = [min, glam::vec2(max.x, min.y), max, glam::vec2(min.x, max.y)];
+            draw_outline(
+                &corners,
+                thickness,
+                [1., 1., 1., 0.5],
+                &mut verts,
+                &mut faces,
+            );
+        }
+
+        // The view can be rotated so use its actual corners
         let proj_inv = self.calc_proj_matrix().inverse();
-        let world_pos = (proj_inv * pos).xy();
-        (world_pos.x, world_pos.y)
-    }
+        let corners: Vec<_> = [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)]
+            .into_iter()
+            .map(|(x, y)| (proj_inv * glam::vec4(x, y, 0., 1.)).xy())
+            .collect();
+        draw_outline(
+            &corners,
+            thickness,
+            [1., 0.8, 0., 1.],
+            &mut verts,
+            &mut faces,
+        );
 
-    fn process_req(&mut self) {
-        let req = self.req_socket.recv_multipart(zmq::DONTWAIT).unwrap();
+        (verts, faces)
+    }
 
-        assert_eq!(req[0].len(), 1);
-        assert_eq!(req.len(), 2);
-        let cmd = Command::from_u8(req[0][0]);
-        let payload = req[1].clone();
+    fn process_req(&mut self, req: ReqCommand) -> Vec<u8> {
+        if !req.is_read_only() {
+            self.mark_dirty();
+        }
 
         let mut reply = vec![];
 
-        match cmd {
-            Command::Hello => {
-                assert_eq!(payload.len(), 0);
+        match req {
+            ReqCommand::Hello => {
                 "hello".encode(&mut reply).unwrap();
             }
-            Command::ScreenToWorld => {
-                let mut cur = Cursor::new(payload);
-                let x = f32::decode(&mut cur).unwrap();
-                let y = f32::decode(cur).unwrap();
+            ReqCommand::ScreenToWorld { x, y } => {
                 //debug!("screen_to_world({}, {})", x, y);
                 let (x, y) = self.screen_to_world(x, y);
                 x.encode(&mut reply).unwrap();
                 y.encode(&mut reply).unwrap();
             }
-            Command::GetLayers => {
+            ReqCommand::GetLayers => {
                 debug!("get_layers()");
                 let layer_names: Vec<String> = self.layers.keys().cloned().collect();
                 layer_names.encode(&mut reply).unwrap();
             }
-            Command::DeleteLayer => {
-                let layer_name: String = deserialize(&payload).unwrap();
+            ReqCommand::DeleteLayer { layer_name } => {
                 debug!("delete_layer({})", layer_name);
-                let is_success = self.layers.remove(&layer_name).is_some();
-                is_success.encode(&mut reply).unwrap();
+                let status = if !self.layers.contains_key(&layer_name) {
+                    EditStatus::Failed
+                } else if self.is_subtree_locked(&layer_name) {
+                    warn!("delete_layer({}) rejected: layer is locked", layer_name);
+                    EditStatus::Locked
+                } else {
+                    // Deleting a group deletes everything inside it
+                    self.layers
+                        .retain(|name, _| !is_in_subtree(name, &layer_name));
+                    EditStatus::Ok
+                };
+                (status as u8).encode(&mut reply).unwrap();
             }
-            Command::ShowLayer => {
-                let layer_name: String = deserialize(&payload).unwrap();
+            ReqCommand::ShowLayer { layer_name } => {
                 debug!("show_layer({})", layer_name);
                 let is_success = match self.layers.get_mut(&layer_name) {
                     Some(layer) => {
@@ -396,8 +3062,7 @@ impl Stage {
                 };
                 is_success.encode(&mut reply).unwrap();
             }
-            Command::HideLayer => {
-                let layer_name: String = deserialize(&payload).unwrap();
+            ReqCommand::HideLayer { layer_name } => {
                 debug!("hide_layer({})", layer_name);
                 let is_success = match self.layers.get_mut(&layer_name) {
                     Some(layer) => {
@@ -408,48 +3073,371 @@ impl Stage {
                 };
                 is_success.encode(&mut reply).unwrap();
             }
-            Command::SetLayerPos => {
-                let mut cur = Cursor::new(payload);
-                let layer_name = String::decode(&mut cur).unwrap();
-                let x = f32::decode(&mut cur).unwrap();
-                let y = f32::decode(cur).unwrap();
+            ReqCommand::SetLayerPos { layer_name, x, y } => {
                 //debug!("set_layer_pos({}, {}, {})", layer_name, x, y);
                 let model = glam::Mat4::from_translation(glam::Vec3::new(x, y, 0.));
-                let is_success = match self.layers.get_mut(&layer_name) {
+                let status = self.edit_layer(&layer_name, true, |layer| layer.model = model);
+                (status as u8).encode(&mut reply).unwrap();
+            }
+            ReqCommand::ScreenSize => {
+                debug!("screen_size()");
+                let (screen_width, screen_height) = window::screen_size();
+                screen_width.encode(&mut reply).unwrap();
+                screen_height.encode(&mut reply).unwrap();
+            }
+            ReqCommand::SetLayerOpacity {
+                layer_name,
+                opacity,
+            } => {
+                debug!("set_layer_opacity({}, {})", layer_name, opacity);
+                let status = self.edit_layer(&layer_name, true, |layer| {
+                    layer.opacity = opacity.clamp(0., 1.);
+                });
+                (status as u8).encode(&mut reply).unwrap();
+            }
+            ReqCommand::LockLayer { layer_name, owner } => {
+                debug!("lock_layer({}, {})", layer_name, owner);
+                let status = match self.layers.get_mut(&layer_name) {
+                    // Someone else already holds the lock
+                    Some(layer) if layer.is_locked && layer.owner.as_ref() != Some(&owner) => {
+                        EditStatus::Locked
+                    }
                     Some(layer) => {
-                        layer.model = model;
+                        layer.is_locked = true;
+                        layer.owner = Some(owner);
+                        EditStatus::Ok
+                    }
+                    None => EditStatus::Failed,
+                };
+                (status as u8).encode(&mut reply).unwrap();
+            }
+            ReqCommand::UnlockLayer { layer_name, owner } => {
+                debug!("unlock_layer({}, {})", layer_name, owner);
+                let status = match self.layers.get_mut(&layer_name) {
+                    Some(layer) if layer.owner.as_ref() == Some(&owner) => {
+                        layer.is_locked = false;
+                        EditStatus::Ok
+                    }
+                    // Someone else holds the lock
+                    Some(layer) if layer.is_locked => EditStatus::Locked,
+                    _ => EditStatus::Failed,
+                };
+                (status as u8).encode(&mut reply).unwrap();
+            }
+            ReqCommand::RenameLayer { old_name, new_name } => {
+                debug!("rename_layer({}, {})", old_name, new_name);
+                let status = self.rename_layer(&old_name, &new_name);
+                (status as u8).encode(&mut reply).unwrap();
+            }
+            ReqCommand::CloneLayer { src_name, dst_name } => {
+                debug!("clone_layer({}, {})", src_name, dst_name);
+                let status = self.clone_layer(&src_name, &dst_name);
+                (status as u8).encode(&mut reply).unwrap();
+            }
+            ReqCommand::MergeLayers {
+                src_names,
+                dst_name,
+            } => {
+                debug!("merge_layers({:?}, {})", src_names, dst_name);
+                let status = self.merge_layers(&src_names, dst_name);
+                (status as u8).encode(&mut reply).unwrap();
+            }
+            ReqCommand::ClearLayer { layer_name } => {
+                debug!("clear_layer({})", layer_name);
+                // Keep the layer itself so its model, flags and metadata survive
+                let status = self.edit_layer(&layer_name, false, |layer| {
+                    layer.clear_lines();
+                    layer.strokes = 0;
+                });
+                (status as u8).encode(&mut reply).unwrap();
+            }
+            ReqCommand::GetLayerInfo { layer_name } => {
+                debug!("get_layer_info({})", layer_name);
+                let is_success = self.layers.contains_key(&layer_name);
+                is_success.encode(&mut reply).unwrap();
+                if is_success {
+                    reply.append(&mut self.layer_info(&layer_name));
+                }
+            }
+            ReqCommand::FitAll { duration } => {
+                debug!("fit_all({})", duration);
+                let is_success = match self.visible_content_bounds() {
+                    Some(bounds) => {
+                        let camera = self.fit_camera(bounds, DEFAULT_FIT_PADDING);
+                        self.animate_camera(camera, duration);
                         true
                     }
                     None => false,
                 };
                 is_success.encode(&mut reply).unwrap();
             }
-            Command::ScreenSize => {
-                debug!("screen_size()");
-                let (screen_width, screen_height) = window::screen_size();
-                screen_width.encode(&mut reply).unwrap();
-                screen_height.encode(&mut reply).unwrap();
+            ReqCommand::FitLayer {
+                layer_name,
+                padding,
+                duration,
+            } => {
+                debug!("fit_layer({}, {}, {})", layer_name, padding, duration);
+                let is_success = match self.subtree_world_bounds(&layer_name) {
+                    Some(bounds) => {
+                        let camera = self.fit_camera(bounds, padding);
+                        self.animate_camera(camera, duration);
+                        true
+                    }
+                    None => false,
+                };
+                is_success.encode(&mut reply).unwrap();
+            }
+            ReqCommand::SetBackground(params) => {
+                debug!("set_background({:?})", params);
+                let mode = BackgroundMode::from_u8(params.mode);
+                let is_success = match mode {
+                    Some(mode) if params.spacing > 0. => {
+                        self.background = Background {
+                            mode,
+                            color: [params.r, params.g, params.b, params.a],
+                            line_color: [
+                                params.line_r,
+                                params.line_g,
+                                params.line_b,
+                                params.line_a,
+                            ],
+                            spacing: params.spacing,
+                        };
+                        true
+                    }
+                    _ => false,
+                };
+                is_success.encode(&mut reply).unwrap();
+            }
+            ReqCommand::SetLayerScreenSpace {
+                layer_name,
+                is_screen_space,
+            } => {
+                debug!(
+                    "set_layer_screen_space({}, {})",
+                    layer_name, is_screen_space
+                );
+                let status = self.edit_layer(&layer_name, true, |layer| {
+                    layer.is_screen_space = is_screen_space;
+                });
+                self.update_chunking();
+                (status as u8).encode(&mut reply).unwrap();
+            }
+            ReqCommand::SaveBookmark { name } => {
+                debug!("save_bookmark({})", name);
+                self.bookmarks.insert(name, self.camera);
+                self.write_bookmarks();
+                true.encode(&mut reply).unwrap();
+            }
+            ReqCommand::GotoBookmark { name, duration } => {
+                debug!("goto_bookmark({}, {})", name, duration);
+                let is_success = match self.bookmarks.get(&name) {
+                    Some(camera) => {
+                        self.animate_camera(*camera, duration);
+                        true
+                    }
+                    None => false,
+                };
+                is_success.encode(&mut reply).unwrap();
+            }
+            ReqCommand::ListBookmarks => {
+                debug!("list_bookmarks()");
+                reply.append(&mut Self::encode_bookmarks(&self.bookmarks));
+            }
+            ReqCommand::DeleteBookmark { name } => {
+                debug!("delete_bookmark({})", name);
+                let is_success = self.bookmarks.remove(&name).is_some();
+                if is_success {
+                    self.write_bookmarks();
+                }
+                is_success.encode(&mut reply).unwrap();
+            }
+            ReqCommand::SetFrame(params) => {
+                debug!("set_frame({:?})", params);
+                let (p1, p2) = (
+                    glam::vec2(params.x1, params.y1),
+                    glam::vec2(params.x2, params.y2),
+                );
+                let frame = Frame {
+                    bounds: (p1.min(p2), p1.max(p2)),
+                    order: params.order,
+                };
+                self.frames.insert(params.name, frame);
+                true.encode(&mut reply).unwrap();
+            }
+            ReqCommand::DeleteFrame { name } => {
+                debug!("delete_frame({})", name);
+                let is_success = self.frames.remove(&name).is_some();
+                if self.current_slide.as_ref() == Some(&name) {
+                    self.current_slide = None;
+                }
+                is_success.encode(&mut reply).unwrap();
+            }
+            ReqCommand::ListFrames => {
+                debug!("list_frames()");
+                let slide_names = self.slide_names();
+                VarInt(slide_names.len() as u64).encode(&mut reply).unwrap();
+                for name in slide_names {
+                    let frame = &self.frames[&name];
+                    let (min, max) = frame.bounds;
+                    name.encode(&mut reply).unwrap();
+                    min.x.encode(&mut reply).unwrap();
+                    min.y.encode(&mut reply).unwrap();
+                    max.x.encode(&mut reply).unwrap();
+                    max.y.encode(&mut reply).unwrap();
+                    frame.order.encode(&mut reply).unwrap();
+                }
+            }
+            ReqCommand::GotoSlide { name, duration } => {
+                debug!("goto_slide({}, {})", name, duration);
+                let is_success = self.goto_slide(&name, duration);
+                is_success.encode(&mut reply).unwrap();
+            }
+            ReqCommand::NextSlide { duration } => {
+                debug!("next_slide({})", duration);
+                let is_success = self.step_slide(true, duration);
+                is_success.encode(&mut reply).unwrap();
+            }
+            ReqCommand::PrevSlide { duration } => {
+                debug!("prev_slide({})", duration);
+                let is_success = self.step_slide(false, duration);
+                is_success.encode(&mut reply).unwrap();
+            }
+            ReqCommand::FollowView { peer } => {
+                debug!("follow_view({})", peer);
+                // Empty stops following
+                self.followed_peer = (!peer.is_empty()).then_some(peer);
+                true.encode(&mut reply).unwrap();
+            }
+            ReqCommand::SetViewport(params) => {
+                debug!("set_viewport({:?})", params);
+                let (p1, p2) = (
+                    glam::vec2(params.x1, params.y1),
+                    glam::vec2(params.x2, params.y2),
+                );
+                let (min, max) = (
+                    p1.min(p2).max(glam::Vec2::ZERO),
+                    p1.max(p2).min(glam::Vec2::ONE),
+                );
+                let is_success = min.x < max.x && min.y < max.y;
+                if is_success {
+                    // New viewports start out showing the same as the main view
+                    let camera = self.camera;
+                    let viewport = self.viewports.entry(params.name).or_insert(Viewport {
+                        rect: (min, max),
+                        camera,
+                        layer_filter: vec![],
+                    });
+                    viewport.rect = (min, max);
+                }
+                is_success.encode(&mut reply).unwrap();
+            }
+            ReqCommand::DeleteViewport { name } => {
+                debug!("delete_viewport({})", name);
+                let is_success = self.viewports.remove(&name).is_some();
+                is_success.encode(&mut reply).unwrap();
             }
-            _ => {
-                panic!("use sub socket instead!");
+            ReqCommand::SetViewportView(params) => {
+                debug!("set_viewport_view({:?})", params);
+                let camera = Camera::from_view(params.x, params.y, params.zoom, params.rotation);
+                if camera.is_none() {
+                    warn!("set_viewport_view() rejected: invalid view");
+                }
+                let is_success = match (self.viewports.get_mut(&params.name), camera) {
+                    (Some(viewport), Some(camera)) => {
+                        viewport.camera = camera;
+                        true
+                    }
+                    _ => false,
+                };
+                is_success.encode(&mut reply).unwrap();
+            }
+            ReqCommand::SetViewportLayers { name, layer_filter } => {
+                debug!("set_viewport_layers({}, {:?})", name, layer_filter);
+                let is_success = match self.viewports.get_mut(&name) {
+                    Some(viewport) => {
+                        viewport.layer_filter = layer_filter;
+                        true
+                    }
+                    None => false,
+                };
+                is_success.encode(&mut reply).unwrap();
+            }
+            ReqCommand::SetSlideClipping { is_clipping } => {
+                debug!("set_slide_clipping({})", is_clipping);
+                self.is_slide_clipping = is_clipping;
+                true.encode(&mut reply).unwrap();
+            }
+            ReqCommand::GetView => {
+                debug!("get_view()");
+                self.camera.center.x.encode(&mut reply).unwrap();
+                self.camera.center.y.encode(&mut reply).unwrap();
+                self.camera.zoom.encode(&mut reply).unwrap();
+                self.camera.rotation.encode(&mut reply).unwrap();
+            }
+            ReqCommand::WorldToScreen { x, y } => {
+                //debug!("world_to_screen({}, {})", x, y);
+                let (x, y) = self.world_to_screen(x, y);
+                x.encode(&mut reply).unwrap();
+                y.encode(&mut reply).unwrap();
+            }
+            ReqCommand::SetLayerMetadata {
+                layer_name,
+                key,
+                value,
+            } => {
+                debug!("set_layer_metadata({}, {}, {})", layer_name, key, value);
+                let status = self.edit_layer(&layer_name, false, |layer| {
+                    // An empty value removes the key
+                    if value.is_empty() {
+                        layer.metadata.remove(&key);
+                    } else {
+                        layer.metadata.insert(key, value);
+                    }
+                });
+                (status as u8).encode(&mut reply).unwrap();
+            }
+            ReqCommand::GetNetStats => {
+                debug!("get_net_stats()");
+                let stats = &self.net_stats;
+                for counter in [
+                    &stats.received,
+                    &stats.stalls,
+                    &stats.queued,
+                    &stats.max_queued,
+                    &stats.deferred_frames,
+                ] {
+                    counter.load(Ordering::Relaxed).encode(&mut reply).unwrap();
+                }
             }
         }
 
-        self.req_socket.send(reply, 0).unwrap();
+        reply
     }
 
-    fn process_sub(&mut self) {
-        let req = self.sub_socket.recv_multipart(zmq::DONTWAIT).unwrap();
+    fn process_sub(&mut self, sub: SubCommand) {
+        self.mark_dirty();
 
-        assert_eq!(req[0].len(), 1);
-        assert_eq!(req.len(), 2);
-        let cmd = Command::from_u8(req[0][0]);
-        let payload = req[1].clone();
+        if sub.is_manual_camera_move() {
+            self.camera_anim = None;
+            self.followed_peer = None;
+        }
 
-        match cmd {
-            Command::DrawLine => {
-                let params: RequestDrawLine = deserialize(&payload).unwrap();
+        match sub {
+            SubCommand::DrawLine(params) => {
                 //debug!("draw_line({:?})", params);
+                // There's no reply on this socket, so tell whoever's listening instead
+                if self.is_layer_locked(&params.layer_name) {
+                    warn!(
+                        "draw_line() rejected: layer {} is locked",
+                        params.layer_name
+                    );
+                    let mut event = vec![];
+                    (PubEvents::LayerLocked as u8).encode(&mut event).unwrap();
+                    params.layer_name.encode(&mut event).unwrap();
+                    self.pub_socket.send(event, 0).unwrap();
+                    return;
+                }
                 self.draw_line(
                     params.layer_name,
                     params.x1,
@@ -463,21 +3451,98 @@ impl Stage {
                     params.a,
                 )
             }
-            Command::Pan => {
-                //let params: RequestPan = deserialize(&payload).unwrap();
-                let mut cur = Cursor::new(payload);
-                let x = f32::decode(&mut cur).unwrap();
-                let y = f32::decode(cur).unwrap();
+            SubCommand::Pan { x, y } => {
                 debug!("pan({}, {})", x, y);
+                if !x.is_finite() || !y.is_finite() {
+                    warn!("pan() rejected: invalid offset");
+                    return;
+                }
                 self.pan(x, y)
             }
-            Command::Zoom => {
-                let scale: f32 = deserialize(&payload).unwrap();
+            SubCommand::Zoom { scale } => {
                 debug!("zoom({})", scale);
+                if !scale.is_finite() || scale <= 0. {
+                    warn!("zoom() rejected: invalid scale");
+                    return;
+                }
                 self.zoom(scale)
             }
-            _ => {
-                panic!("only for no reply messages!")
+            SubCommand::SetView {
+                x,
+                y,
+                zoom,
+                rotation,
+                duration,
+            } => {
+                debug!(
+                    "set_view({}, {}, {}, {}, {})",
+                    x, y, zoom, rotation, duration
+                );
+                let Some(camera) = Camera::from_view(x, y, zoom, rotation) else {
+                    warn!("set_view() rejected: invalid view");
+                    return;
+                };
+                self.animate_camera(camera, duration);
+            }
+            SubCommand::ZoomAt { scale, x, y } => {
+                debug!("zoom_at({}, {}, {})", scale, x, y);
+                if ![scale, x, y].iter().all(|val| val.is_finite()) || scale <= 0. {
+                    warn!("zoom_at() rejected: invalid scale or point");
+                    return;
+                }
+                self.camera.zoom_at(scale, glam::vec2(x, y));
+            }
+            SubCommand::ZoomAtScreen { scale, x, y } => {
+                debug!("zoom_at_screen({}, {}, {})", scale, x, y);
+                if ![scale, x, y].iter().all(|val| val.is_finite()) || scale <= 0. {
+                    warn!("zoom_at_screen() rejected: invalid scale or point");
+                    return;
+                }
+                let (x, y) = self.screen_to_world(x, y);
+                self.camera.zoom_at(scale, glam::vec2(x, y));
+            }
+            SubCommand::Rotate { angle } => {
+                debug!("rotate({})", angle);
+                if !angle.is_finite() {
+                    warn!("rotate() rejected: invalid angle");
+                    return;
+                }
+                let center = self.camera.center;
+                self.camera.rotate_at(angle, center);
+            }
+            SubCommand::RotateAt { angle, x, y } => {
+                debug!("rotate_at({}, {}, {})", angle, x, y);
+                if ![angle, x, y].iter().all(|val| val.is_finite()) {
+                    warn!("rotate_at() rejected: invalid angle or point");
+                    return;
+                }
+                self.camera.rotate_at(angle, glam::vec2(x, y));
+            }
+            SubCommand::RemoteView(params) => {
+                //debug!("remote_view({:?})", params);
+                if self.followed_peer.as_ref() != Some(&params.peer) {
+                    return;
+                }
+                let Some(camera) =
+                    Camera::from_view(params.x, params.y, params.zoom, params.rotation)
+                else {
+                    warn!("remote_view() rejected: invalid view from {}", params.peer);
+                    return;
+                };
+                self.camera_anim = None;
+                self.camera = camera;
+            }
+            SubCommand::SetMinimap { is_visible } => {
+                debug!("set_minimap({})", is_visible);
+                self.is_minimap_visible = is_visible;
+            }
+            SubCommand::SetAntialiasing { is_enabled } => {
+                debug!("set_antialiasing({})", is_enabled);
+                self.renderer.is_antialiased = is_enabled;
+            }
+            SubCommand::SetMinStrokeWidth { width } => {
+                debug!("set_min_stroke_width({})", width);
+                self.renderer.min_stroke_width = width.max(0.);
             }
         }
     }
@@ -485,98 +3550,124 @@ impl Stage {
 
 impl EventHandler for Stage {
     fn update(&mut self) {
+        self.update_camera_animation();
+
+        // The event loop only runs when the network thread, an animation or
+        // a window event schedules an update, so an idle canvas uses no CPU.
+        // Anything over budget is left for the next frame.
         let instant = time::Instant::now();
-        let mut remaining = 20i64;
         loop {
-            // https://github.com/johnliu55tw/rust-zmq-poller/blob/master/src/main.rs
-            let mut items = [
-                self.req_socket.as_poll_item(zmq::POLLIN),
-                self.sub_socket.as_poll_item(zmq::POLLIN),
-            ];
-            let _rc = zmq::poll(&mut items, remaining).unwrap();
-
-            // Rust borrow checker things
-            let (is_item0_readable, is_item1_readable) = (items[0].is_readable(), items[1].is_readable());
-            drop(items);
-
-            if is_item0_readable {
-                self.process_req()
+            match self.command_rx.try_recv() {
+                Ok(msg) => self.apply_command(msg),
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    error!("Network thread stopped");
+                    window::request_quit();
+                    break;
+                }
             }
-            if is_item1_readable {
-                self.process_sub()
-            }
-
-            remaining -= instant.elapsed().as_millis() as i64;
-            if remaining <= 0 {
+            if instant.elapsed() > COMMAND_BUDGET {
+                if self.net_stats.queued.load(Ordering::Relaxed) > 0 {
+                    self.net_stats
+                        .deferred_frames
+                        .fetch_add(1, Ordering::Relaxed);
+                    window::schedule_update();
+                }
                 break;
             }
         }
+
+        self.publish_view();
     }
 
     // Only do drawing here. Apps might not call this when minimized.
     fn draw(&mut self) {
-        let clear = PassAction::clear_color(0., 0., 0., 1.);
-        self.ctx.begin_default_pass(clear);
-        self.ctx.end_render_pass();
+        self.renderer.begin_frame(self.background.color);
 
-        let (screen_width, screen_height) = window::screen_size();
         //// Preserve the same size irregardless of the screen size
         //let proj = self.proj * glam::Mat4::from_scale(glam::Vec3::new(2500./screen_width, 2500./screen_height, 1.));
         let proj = self.calc_proj_matrix();
+        let screen_proj = self.calc_screen_proj_matrix();
+        let identity = glam::Mat4::IDENTITY;
 
-        for layer in self.layers.values() {
-            if layer.is_hidden {
-                continue;
-            }
-
-            let vertex_buffer = self.ctx.new_buffer(
-                BufferType::VertexBuffer,
-                BufferUsage::Immutable,
-                BufferSource::slice(&layer.verts),
-            );
-
-            let bufsrc = unsafe {
-                BufferSource::pointer(
-                    layer.faces.as_ptr() as _,
-                    std::mem::size_of_val(&layer.faces[..]),
-                    std::mem::size_of::<u32>(),
-                )
-            };
-
-            let index_buffer =
-                self.ctx
-                    .new_buffer(BufferType::IndexBuffer, BufferUsage::Immutable, bufsrc);
-
-            let bindings = Bindings {
-                vertex_buffers: vec![vertex_buffer],
-                index_buffer: index_buffer,
-                images: vec![self.white_texture],
-            };
+        // Paper pattern goes underneath everything else
+        self.renderer.set_view(proj, ScreenRect::full_screen());
+        let (verts, faces) = self.background_mesh(&self.camera, ScreenRect::full_screen());
+        self.renderer.draw_mesh(&verts, &faces, &identity, 1.);
 
-            //self.ctx.begin_default_pass(Default::default());
-            self.ctx.begin_default_pass(PassAction::Nothing);
+        let (overlays, layers): (Vec<_>, Vec<_>) = self
+            .layers
+            .iter()
+            .filter(|(_, layer)| !layer.chunks.is_empty())
+            .filter_map(|(name, layer)| Some((name, layer, self.resolve_layer(name)?)))
+            .partition(|(_, _, resolved)| resolved.is_screen_space);
 
-            self.ctx.apply_pipeline(&self.pipeline);
-            self.ctx
-                .apply_viewport(0, 0, screen_width as i32, screen_height as i32);
-            self.ctx
-                .apply_scissor_rect(0, 0, screen_width as i32, screen_height as i32);
-            self.ctx.apply_bindings(&bindings);
+        let clip = self
+            .slide_clip_rect()
+            .filter(|_| self.is_slide_clipping)
+            .unwrap_or_else(ScreenRect::full_screen);
+        self.renderer.set_view(proj, clip);
+        // Only draw what's on screen
+        let view = self.view_world_bounds();
+        for (_, layer, resolved) in &layers {
+            let level = self.lod_level(&self.camera, resolved.model);
+            for chunk in layer.visible_chunks(resolved.model, view) {
+                let (tag, mesh) = chunk.mesh(level);
+                self.renderer
+                    .draw_kept_mesh(tag, mesh, &resolved.model, resolved.opacity);
+            }
+        }
+        for viewport in self.viewports.values() {
+            let rect = viewport.screen_rect();
 
-            let mut uniforms_data = [0u8; 128];
-            let data: [u8; 64] = unsafe { std::mem::transmute_copy(&layer.model) };
-            uniforms_data[0..64].copy_from_slice(&data);
-            let data: [u8; 64] = unsafe { std::mem::transmute_copy(&proj) };
-            uniforms_data[64..].copy_from_slice(&data);
-            assert_eq!(128, 2 * UniformType::Mat4.size());
+            // Hide the main view underneath and give it a border
+            self.renderer
+                .set_view(screen_proj, ScreenRect::full_screen());
+            let (verts, faces) = self.viewport_frame_mesh(rect);
+            self.renderer.draw_mesh(&verts, &faces, &identity, 1.);
 
-            self.ctx
-                .apply_uniforms_from_bytes(uniforms_data.as_ptr(), uniforms_data.len());
+            let viewport_proj = self.calc_camera_proj_matrix(&viewport.camera, rect);
+            self.renderer.set_view(viewport_proj, rect);
+            let (verts, faces) = self.background_mesh(&viewport.camera, rect);
+            self.renderer.draw_mesh(&verts, &faces, &identity, 1.);
 
-            self.ctx.draw(0, 3 * layer.faces.len() as i32, 1);
-            self.ctx.end_render_pass();
+            let view = self.camera_world_bounds(&viewport.camera, rect);
+            let shown = layers
+                .iter()
+                .filter(|(name, _, _)| viewport.shows_layer(name));
+            for (_, layer, resolved) in shown {
+                let level = self.lod_level(&viewport.camera, resolved.model);
+                for chunk in layer.visible_chunks(resolved.model, view) {
+                    let (tag, mesh) = chunk.mesh(level);
+                    self.renderer
+                        .draw_kept_mesh(tag, mesh, &resolved.model, resolved.opacity);
+                }
+            }
         }
-        self.ctx.commit_frame();
+        if self.is_minimap_visible {
+            let minimap_proj = self.calc_minimap_proj_matrix();
+            self.renderer.set_view(minimap_proj, self.minimap_rect());
+            let (verts, faces) = self.minimap_mesh();
+            self.renderer.draw_mesh(&verts, &faces, &identity, 1.);
+        }
+        // Overlays go on top
+        self.renderer
+            .set_view(screen_proj, ScreenRect::full_screen());
+        for (_, layer, resolved) in overlays {
+            for chunk in &layer.chunks {
+                let (tag, mesh) = chunk.mesh(0);
+                self.renderer
+                    .draw_kept_mesh(tag, mesh, &resolved.model, resolved.opacity);
+            }
+        }
+        self.renderer.end_frame();
+    }
+
+    fn resize_event(&mut self, _width: f32, _height: f32) {
+        self.mark_dirty();
+    }
+    fn window_restored_event(&mut self) {
+        self.mark_dirty();
     }
 
     fn key_down_event(&mut self, keycode: KeyCode, modifiers: KeyMods, repeat: bool) {
@@ -728,9 +3819,10 @@ impl EventHandler for Stage {
         }
     }
     fn mouse_motion_event(&mut self, x: f32, y: f32) {
-        let (screen_width, screen_height) = window::screen_size();
-        let (x, y) = (x / screen_width, y / screen_height);
-        let (x, y) = self.screen_to_world(x, y);
+        if !self.minimap_presses.is_empty() {
+            return;
+        }
+        let (x, y) = self.mouse_to_world(x, y);
 
         let mut event = vec![];
         (PubEvents::MouseMotion as u8).encode(&mut event).unwrap();
@@ -751,9 +3843,27 @@ impl EventHandler for Stage {
         //self.proj *= glam::Mat4::from_scale(glam::Vec3::new(scale, scale, scale));
     }
     fn mouse_button_down_event(&mut self, button: MouseButton, x: f32, y: f32) {
-        let (screen_width, screen_height) = window::screen_size();
-        let (x, y) = (x / screen_width, y / screen_height);
-        let (x, y) = self.screen_to_world(x, y);
+        // Clicks on the minimap navigate instead of reaching the board
+        if self.is_minimap_visible && self.minimap_rect().contains(x, y) {
+            let pos = self.minimap_to_world(x, y);
+            let to = Camera {
+                center: pos,
+                ..self.camera
+            };
+            self.animate_camera(to, MINIMAP_JUMP_DURATION);
+
+            let mut event = vec![];
+            (PubEvents::MinimapClick as u8).encode(&mut event).unwrap();
+            button.to_u8().encode(&mut event).unwrap();
+            pos.x.encode(&mut event).unwrap();
+            pos.y.encode(&mut event).unwrap();
+            debug!("MinimapClick => ({:?}, {}, {})", button, pos.x, pos.y);
+            self.pub_socket.send(event, 0).unwrap();
+            self.minimap_presses.push(button);
+            return;
+        }
+
+        let (x, y) = self.mouse_to_world(x, y);
 
         let mut event = vec![];
         (PubEvents::MouseButtonDown as u8)
@@ -766,9 +3876,16 @@ impl EventHandler for Stage {
         self.pub_socket.send(event, 0).unwrap();
     }
     fn mouse_button_up_event(&mut self, button: MouseButton, x: f32, y: f32) {
-        let (screen_width, screen_height) = window::screen_size();
-        let (x, y) = (x / screen_width, y / screen_height);
-        let (x, y) = self.screen_to_world(x, y);
+        // Finishes a minimap click, which the board never saw start
+        if let Some(idx) = self
+            .minimap_presses
+            .iter()
+            .position(|&pressed| pressed == button)
+        {
+            self.minimap_presses.remove(idx);
+            return;
+        }
+        let (x, y) = self.mouse_to_world(x, y);
 
         let mut event = vec![];
         (PubEvents::MouseButtonUp as u8).encode(&mut event).unwrap();
@@ -805,16 +3922,17 @@ fn draw_line(
     let tx = nx / tlen;
     let ty = ny / tlen;
 
-    // We don't care about UV coords
-    let uv = [0f32; 2];
+    // UV holds the half width and which edge this is, for anti-aliasing
+    let half_width = thickness * 0.5;
+    let (uv_left, uv_right) = ([half_width, 1.], [half_width, -1.]);
 
     #[rustfmt::skip]
     (
         vec![
-            Vertex { pos: [x1 + tx, y1 + ty], color, uv, },
-            Vertex { pos: [x1 - tx, y1 - ty], color, uv, },
-            Vertex { pos: [x2 + tx, y2 + ty], color, uv, },
-            Vertex { pos: [x2 - tx, y2 - ty], color, uv, },
+            Vertex { pos: [x1 + tx, y1 + ty], color, uv: uv_left, normal: [tx, ty] },
+            Vertex { pos: [x1 - tx, y1 - ty], color, uv: uv_right, normal: [-tx, -ty] },
+            Vertex { pos: [x2 + tx, y2 + ty], color, uv: uv_left, normal: [tx, ty] },
+            Vertex { pos: [x2 - tx, y2 - ty], color, uv: uv_right, normal: [-tx, -ty] },
         ],
         vec![
             Face { idxs: [0, 1, 2] },
@@ -850,18 +3968,39 @@ fn main() {
         platform: miniquad::conf::Platform {
             linux_backend: miniquad::conf::LinuxBackend::WaylandWithX11Fallback,
             wayland_use_fallback_decorations: false,
+            // Stage wakes it up with schedule_update() when there's something to draw
+            blocking_event_loop: true,
             ..Default::default()
         },
         ..Default::default()
     };
-    let metal = std::env::args().nth(1).as_deref() == Some("metal");
+    // Multisampling smooths the edges of everything, not just strokes
+    if std::env::args().any(|arg| arg == "--msaa") {
+        conf.sample_count = 4;
+    }
+    let metal = std::env::args().any(|arg| arg == "metal");
     conf.platform.apple_gfx_api = if metal {
         conf::AppleGfxApi::Metal
     } else {
         conf::AppleGfxApi::OpenGl
     };
 
-    miniquad::start(conf, || Box::new(Stage::new()));
+    let view_scaling = if std::env::args().any(|arg| arg == "--legacy-scale") {
+        ViewScaling::Legacy
+    } else {
+        ViewScaling::Dpi
+    };
+
+    // Bookmarks are loaded from here at startup and written back on every change
+    let mut args = std::env::args();
+    let bookmarks_path = args
+        .position(|arg| arg == "--bookmarks")
+        .and_then(|_| args.next())
+        .map(PathBuf::from);
+
+    miniquad::start(conf, move || {
+        Box::new(Stage::new(view_scaling, bookmarks_path))
+    });
 }
 
 mod shader {
@@ -871,27 +4010,57 @@ mod shader {
     attribute vec2 in_pos;
     attribute vec4 in_color;
     attribute vec2 in_uv;
+    attribute vec2 in_normal;
 
     varying lowp vec4 color;
-    varying lowp vec2 uv;
+    varying mediump vec2 uv;
 
-    uniform mat4 Model;
     uniform mat4 Projection;
+    uniform mat4 Model;
+    uniform vec2 PixelScale;
+    uniform float MinStrokeWidth;
+    uniform float Antialiasing;
+    uniform float Opacity;
 
     void main() {
-        gl_Position = Projection * Model * vec4(in_pos, 0, 1);
-        color = in_color;
-        uv = in_uv;
+        mat4 mvp = Projection * Model;
+        // Physical pixels per model unit, averaged over x and y
+        vec2 x_axis = mvp[0].xy * PixelScale;
+        vec2 y_axis = mvp[1].xy * PixelScale;
+        float pixels_per_unit = sqrt(abs(x_axis.x * y_axis.y - x_axis.y * y_axis.x));
+
+        // Strokes have their half width in in_uv.x and which edge in in_uv.y
+        vec2 pos = in_pos;
+        float half_pixels = in_uv.x * pixels_per_unit;
+        // Widen strokes which would otherwise vanish or flicker when zoomed out
+        float min_half_pixels = MinStrokeWidth / 2.;
+        if (half_pixels > 0. && half_pixels < min_half_pixels) {
+            pos += in_normal * (min_half_pixels / half_pixels - 1.);
+            half_pixels = min_half_pixels;
+        }
+
+        gl_Position = mvp * vec4(pos, 0, 1);
+        color = vec4(in_color.rgb, in_color.a * Opacity);
+        if (Antialiasing > 0. && half_pixels > 0.) {
+            uv = vec2(half_pixels, in_uv.y * half_pixels);
+        } else {
+            uv = vec2(0.);
+        }
     }"#;
 
     pub const GL_FRAGMENT: &str = r#"#version 100
     varying lowp vec4 color;
-    varying lowp vec2 uv;
+    varying mediump vec2 uv;
 
     uniform sampler2D tex;
 
     void main() {
         gl_FragColor = color * texture2D(tex, uv);
+        // Strokes have their half width in uv.x and distance from the
+        // centre in uv.y, both in pixels. Fade out the edge pixel.
+        if (uv.x > 0.) {
+            gl_FragColor.a *= clamp(uv.x - abs(uv.y) + 0.5, 0., 1.);
+        }
     }"#;
 
     pub const METAL: &str = r#"
@@ -901,8 +4070,12 @@ mod shader {
 
     struct Uniforms
     {
-        float4x4 Model;
         float4x4 Projection;
+        float4x4 Model;
+        float2 PixelScale;
+        float MinStrokeWidth;
+        float Antialiasing;
+        float Opacity;
     };
 
     struct Vertex
@@ -910,6 +4083,7 @@ mod shader {
         float2 in_pos   [[attribute(0)]];
         float4 in_color [[attribute(1)]];
         float2 in_uv    [[attribute(2)]];
+        float2 in_normal [[attribute(3)]];
     };
 
     struct RasterizerData
@@ -919,20 +4093,41 @@ mod shader {
         float2 uv [[user(locn1)]];
     };
 
-    vertex RasterizerData vertexShader(Vertex v [[stage_in]])
+    vertex RasterizerData vertexShader(Vertex v [[stage_in]], constant Uniforms& uniforms [[buffer(0)]])
     {
         RasterizerData out;
 
-        out.position = uniforms.Model * uniforms.Projection * float4(v.in_pos.xy, 0.0, 1.0);
-        out.color = v.in_color;
-        out.uv = v.texcoord;
+        float4x4 mvp = uniforms.Projection * uniforms.Model;
+        float2 x_axis = mvp[0].xy * uniforms.PixelScale;
+        float2 y_axis = mvp[1].xy * uniforms.PixelScale;
+        float pixels_per_unit = sqrt(abs(x_axis.x * y_axis.y - x_axis.y * y_axis.x));
+
+        float2 pos = v.in_pos;
+        float half_pixels = v.in_uv.x * pixels_per_unit;
+        float min_half_pixels = uniforms.MinStrokeWidth / 2.;
+        if (half_pixels > 0. && half_pixels < min_half_pixels) {
+            pos += v.in_normal * (min_half_pixels / half_pixels - 1.);
+            half_pixels = min_half_pixels;
+        }
+
+        out.position = mvp * float4(pos, 0.0, 1.0);
+        out.color = float4(v.in_color.rgb, v.in_color.a * uniforms.Opacity);
+        if (uniforms.Antialiasing > 0. && half_pixels > 0.) {
+            out.uv = float2(half_pixels, v.in_uv.y * half_pixels);
+        } else {
+            out.uv = float2(0.);
+        }
 
         return out;
     }
 
     fragment float4 fragmentShader(RasterizerData in [[stage_in]], texture2d<float> tex [[texture(0)]], sampler texSmplr [[sampler(0)]])
     {
-        return in.color * tex.sample(texSmplr, in.uv);
+        float4 color = in.color * tex.sample(texSmplr, in.uv);
+        if (in.uv.x > 0.) {
+            color.a *= clamp(in.uv.x - abs(in.uv.y) + 0.5, 0., 1.);
+        }
+        return color;
     }
 
     "#;
@@ -944,3 +4139,166 @@ mod shader {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(x1: f32, y1: f32, x2: f32, y2: f32) -> Line {
+        Line {
+            start: glam::vec2(x1, y1),
+            end: glam::vec2(x2, y2),
+            thickness: 0.01,
+            color: [1., 0., 0., 1.],
+        }
+    }
+
+    fn push_line(chunk: &mut Chunk, line: Line) {
+        let mesh = line.mesh();
+        let bounds = mesh_bounds(&mesh.0).unwrap();
+        chunk.push_line(line, bounds, mesh);
+    }
+
+    // Positions and indices, since Vertex doesn't compare
+    fn mesh_data((verts, faces): (&[Vertex], &[Face])) -> (Vec<[f32; 2]>, Vec<[u32; 3]>) {
+        (
+            verts.iter().map(|vert| vert.pos).collect(),
+            faces.iter().map(|face| face.idxs).collect(),
+        )
+    }
+
+    #[test]
+    fn simplify_polyline_drops_straight_points() {
+        let points = [
+            glam::vec2(0., 0.),
+            glam::vec2(1., 0.001),
+            glam::vec2(2., 0.),
+        ];
+        let simplified = simplify_polyline(&points, 0.01);
+        assert_eq!(simplified, [glam::vec2(0., 0.), glam::vec2(2., 0.)]);
+    }
+
+    #[test]
+    fn simplify_polyline_keeps_corners() {
+        let points = [glam::vec2(0., 0.), glam::vec2(1., 1.), glam::vec2(2., 0.)];
+        assert_eq!(simplify_polyline(&points, 0.01), points);
+        // Too short to simplify
+        assert_eq!(simplify_polyline(&points[..2], 10.), points[..2]);
+    }
+
+    #[test]
+    fn lod_mesh_grows_like_a_rebuild() {
+        let mut chunk = Chunk::new((glam::Vec2::ZERO, glam::Vec2::ZERO));
+        push_line(&mut chunk, line(0., 0., 0.01, 0.));
+        push_line(&mut chunk, line(0.01, 0., 0.02, 0.0001));
+        // Builds the simplified meshes so the lines below update them
+        chunk.mesh(1);
+        push_line(&mut chunk, line(0.02, 0.0001, 0.03, 0.));
+        push_line(&mut chunk, line(0.03, 0., 0.03, 0.05));
+        // A new run
+        push_line(&mut chunk, line(0.1, 0.1, 0.11, 0.1));
+        push_line(&mut chunk, line(0.11, 0.1, 0.12, 0.1));
+        assert_eq!(chunk.last_run, 4);
+
+        for level in 1..=LOD_LEVELS {
+            let rebuilt = LodMesh::new(&chunk.lines, chunk.last_run, lod_tolerance(level));
+            let (_, mesh) = chunk.mesh(level);
+            assert_eq!(mesh_data(mesh), mesh_data((&rebuilt.verts, &rebuilt.faces)));
+        }
+    }
+
+    #[test]
+    fn lod_mesh_replaces_only_the_last_run() {
+        let lines = [
+            line(0., 0., 1., 0.),
+            line(2., 0., 3., 0.),
+            line(3., 0., 4., 0.),
+        ];
+        let tolerance = 0.1;
+        let mut lod = LodMesh::new(&lines[..2], 1, tolerance);
+        let first_run = mesh_data((&lod.verts[..4], &lod.faces[..2]));
+        lod.set_last_run(&lines[1..], tolerance);
+        // The first run is untouched and the straight second run is one segment
+        assert_eq!(mesh_data((&lod.verts[..4], &lod.faces[..2])), first_run);
+        assert_eq!(lod.verts.len(), 8);
+        assert_eq!(lod.verts[4].pos[0], 2.);
+        assert_eq!(lod.verts[6].pos[0], 4.);
+    }
+
+    fn set_layer_pos_payload() -> Vec<u8> {
+        let mut payload = vec![];
+        "lines".to_string().encode(&mut payload).unwrap();
+        1.5f32.encode(&mut payload).unwrap();
+        (-2f32).encode(&mut payload).unwrap();
+        payload
+    }
+
+    #[test]
+    fn req_command_decodes_payload() {
+        let req = ReqCommand::decode(Command::SetLayerPos, &set_layer_pos_payload()).unwrap();
+        let ReqCommand::SetLayerPos { layer_name, x, y } = req else {
+            panic!("decoded {:?}", req);
+        };
+        assert_eq!((layer_name.as_str(), x, y), ("lines", 1.5, -2.));
+    }
+
+    #[test]
+    fn req_command_rejects_bad_length() {
+        let mut payload = set_layer_pos_payload();
+        payload.push(0);
+        assert!(ReqCommand::decode(Command::SetLayerPos, &payload).is_err());
+        payload.truncate(payload.len() - 2);
+        assert!(ReqCommand::decode(Command::SetLayerPos, &payload).is_err());
+        assert!(ReqCommand::decode(Command::Hello, &[0]).is_err());
+    }
+
+    #[test]
+    fn commands_stay_on_their_socket() {
+        let mut payload = vec![];
+        2f32.encode(&mut payload).unwrap();
+        assert!(SubCommand::decode(Command::Zoom, &payload).is_ok());
+        assert!(ReqCommand::decode(Command::Zoom, &payload).is_err());
+        assert!(SubCommand::decode(Command::Hello, &[]).is_err());
+        assert!(Command::from_u8(200).is_none());
+    }
+
+    #[test]
+    fn ensure_consumed_needs_the_whole_payload() {
+        let payload = [1, 2];
+        let mut cur = Cursor::new(&payload[..]);
+        assert!(ensure_consumed(&cur).is_err());
+        cur.set_position(2);
+        assert!(ensure_consumed(&cur).is_ok());
+    }
+
+    #[test]
+    fn bookmarks_roundtrip() {
+        let bookmarks = BTreeMap::from([
+            (
+                "home".to_string(),
+                Camera::from_view(0., 0., 1., 0.).unwrap(),
+            ),
+            (
+                "far".to_string(),
+                Camera::from_view(-3., 4.5, 0.25, 1.).unwrap(),
+            ),
+        ]);
+        let data = Stage::encode_bookmarks(&bookmarks);
+        assert_eq!(Stage::decode_bookmarks(&data).unwrap(), bookmarks);
+        assert!(Stage::decode_bookmarks(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn bookmarks_skip_invalid_views() {
+        let mut data = vec![];
+        VarInt(2).encode(&mut data).unwrap();
+        for (name, zoom) in [("flat", 0f32), ("ok", 2.)] {
+            name.to_string().encode(&mut data).unwrap();
+            for value in [1f32, 2., zoom, 0.] {
+                value.encode(&mut data).unwrap();
+            }
+        }
+        let bookmarks = Stage::decode_bookmarks(&data).unwrap();
+        assert_eq!(bookmarks.keys().collect::<Vec<_>>(), ["ok"]);
+    }
+}